@@ -0,0 +1,128 @@
+use std::fmt::Write;
+
+use bytesize::ByteSize;
+use meminfo_server::diff::{FrameDiff, Transition};
+use meminfo_server::snapshot::HostMetadata;
+use serde::Serialize;
+
+/// Names a state the way the JSON output does, `missing` standing for `None`.
+fn label<T: Serialize>(state: &Option<T>, missing: &str) -> String {
+    match state {
+        Some(state) => serde_json::to_value(state)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_else(|| "?".to_string()),
+        None => missing.to_string(),
+    }
+}
+
+fn describe(host: &HostMetadata) -> String {
+    format!(
+        "{}, Linux {}, taken at {} (Unix time)",
+        host.hostname, host.kernel_release, host.taken_at
+    )
+}
+
+fn write_transitions<T: Serialize>(
+    out: &mut String,
+    title: &str,
+    changes: &[&Transition<T>],
+    missing: &str,
+    page_size: u64,
+) {
+    writeln!(out, "{:<40} {:>12} {:>12}", title, "frames", "size").unwrap();
+    if changes.is_empty() {
+        writeln!(out, "  none").unwrap();
+    }
+    for transition in changes {
+        let name = format!(
+            "{} -> {}",
+            label(&transition.from, missing),
+            label(&transition.to, missing)
+        );
+        writeln!(
+            out,
+            "  {:<38} {:>12} {:>12}",
+            name,
+            transition.frames,
+            ByteSize::b(transition.frames * page_size).to_string_as(true)
+        )
+        .unwrap();
+    }
+}
+
+/// Formats `diff` with the largest changes first. `total_ranges` is the number of
+/// changed ranges before `diff` was limited to the largest ones.
+pub fn format_diff(
+    diff: &FrameDiff,
+    before: &HostMetadata,
+    after: &HostMetadata,
+    total_ranges: usize,
+) -> String {
+    let page_size = after.page_size;
+    let mut out = String::new();
+    writeln!(out, "before: {}", describe(before)).unwrap();
+    writeln!(out, "after:  {}", describe(after)).unwrap();
+    writeln!(out).unwrap();
+
+    write_transitions(
+        &mut out,
+        "category changes",
+        &diff.category_changes(),
+        "absent",
+        page_size,
+    );
+    writeln!(out).unwrap();
+    write_transitions(
+        &mut out,
+        "LRU list changes",
+        &diff.lru_changes(),
+        "none",
+        page_size,
+    );
+    writeln!(out).unwrap();
+
+    writeln!(
+        out,
+        "{:<40} {:>12} {:>12}",
+        "flag changes", "gained", "lost"
+    )
+    .unwrap();
+    if diff.flags.is_empty() {
+        writeln!(out, "  none").unwrap();
+    }
+    for change in &diff.flags {
+        writeln!(
+            out,
+            "  {:<38} {:>12} {:>12}",
+            change.flag, change.gained, change.lost
+        )
+        .unwrap();
+    }
+    writeln!(out).unwrap();
+
+    writeln!(
+        out,
+        "changed ranges, {} largest of {}",
+        diff.changed_ranges.len(),
+        total_ranges
+    )
+    .unwrap();
+    for range in &diff.changed_ranges {
+        writeln!(
+            out,
+            "  {:#012x}-{:#012x} {:<24} {:>12} {:>12}",
+            range.start_pfn,
+            range.end_pfn - 1,
+            format!(
+                "{} -> {}",
+                label(&range.from, "absent"),
+                label(&range.to, "absent")
+            ),
+            range.end_pfn - range.start_pfn,
+            ByteSize::b((range.end_pfn - range.start_pfn) * page_size).to_string_as(true)
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,346 @@
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+use meminfo_common::proc_page::PageFrameStats;
+use meminfo_common::MeminfoCollectorProxy;
+use meminfo_server::snapshot::{HostMetadata, Snapshot};
+use meminfo_server::MeminfoCollector;
+
+use table::Category;
+
+mod diff;
+mod table;
+
+const USAGE: &str = "Usage: meminfo-cli [--json] [--watch SECONDS] [--category LIST] [--dbus]
+                   [--workers N]
+       meminfo-cli snapshot [--dbus] FILE
+       meminfo-cli diff [--json] [--ranges N] BEFORE AFTER
+
+Prints statistics about all physical page frames. As root, the page frames are read
+in-process, otherwise meminfo-server is asked over D-Bus.
+
+snapshot saves the page frame tables to FILE. diff compares the snapshots BEFORE and
+AFTER, listing how many frames changed their category, LRU list or flags and the
+largest ranges of frames that changed their category.
+
+Options:
+    --json              Print JSON instead of a table, one object per line.
+    --watch SECONDS     Print the statistics again every SECONDS until stopped.
+    --category LIST     Only print the comma-separated categories of LIST, out of
+                        total, lru, mmap, free, buddy, compound, huge, kernel and
+                        other. Defaults to all of them.
+    --dbus              Ask meminfo-server even when running as root.
+    --workers N         Classify the page frames in N threads when reading them
+                        in-process. Defaults to the number of CPUs.
+    --ranges N          List the N largest changed ranges, 10 by default.
+    -h, --help          Print this help.";
+
+struct Options {
+    json: bool,
+    watch: Option<Duration>,
+    categories: Vec<Category>,
+    dbus: bool,
+    workers: Option<usize>,
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut options = Options {
+            json: false,
+            watch: None,
+            categories: Vec::new(),
+            dbus: false,
+            workers: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => options.json = true,
+                "--watch" => {
+                    let seconds = args
+                        .next()
+                        .ok_or("--watch needs an interval")?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+                        .ok_or("--watch needs a positive number of seconds")?;
+                    options.watch = Some(Duration::from_secs_f64(seconds));
+                }
+                "--category" => {
+                    let list = args.next().ok_or("--category needs a list of categories")?;
+                    for name in list.split(',') {
+                        let category = name.trim().parse()?;
+                        if !options.categories.contains(&category) {
+                            options.categories.push(category);
+                        }
+                    }
+                }
+                "--dbus" => options.dbus = true,
+                "--workers" => {
+                    let workers = args
+                        .next()
+                        .ok_or("--workers needs a number of threads")?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|workers| *workers > 0)
+                        .ok_or("--workers needs a positive number of threads")?;
+                    options.workers = Some(workers);
+                }
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+        if options.categories.is_empty() {
+            options.categories = Category::ALL.to_vec();
+        }
+        Ok(options)
+    }
+}
+
+/// Prints statistics in the format chosen by the options.
+struct Printer {
+    json: bool,
+    categories: Vec<Category>,
+    page_size: u64,
+}
+
+impl Printer {
+    fn print(&self, stats: &PageFrameStats) {
+        if self.json {
+            let json = table::to_json(stats, &self.categories)
+                .expect("page frame statistics can be converted to JSON");
+            println!("{}", json);
+        } else {
+            println!(
+                "{}",
+                table::format_table(stats, &self.categories, self.page_size)
+            );
+        }
+    }
+}
+
+struct SnapshotOptions {
+    path: String,
+    dbus: bool,
+}
+
+impl SnapshotOptions {
+    fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut path = None;
+        let mut dbus = false;
+        for arg in args {
+            match arg.as_str() {
+                "--dbus" => dbus = true,
+                _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+        Ok(Self {
+            path: path.ok_or("snapshot needs a file to write")?,
+            dbus,
+        })
+    }
+}
+
+struct DiffOptions {
+    before: String,
+    after: String,
+    json: bool,
+    ranges: usize,
+}
+
+impl DiffOptions {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut paths = Vec::new();
+        let mut json = false;
+        let mut ranges = 10;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => json = true,
+                "--ranges" => {
+                    ranges = args
+                        .next()
+                        .ok_or("--ranges needs a number")?
+                        .parse()
+                        .map_err(|_| "--ranges needs a number")?
+                }
+                _ if !arg.starts_with('-') => paths.push(arg),
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+        match <[String; 2]>::try_from(paths) {
+            Ok([before, after]) => Ok(Self {
+                before,
+                after,
+                json,
+                ranges,
+            }),
+            Err(_) => Err("diff needs the snapshots BEFORE and AFTER".to_string()),
+        }
+    }
+}
+
+fn parsed<T>(options: Result<T, String>) -> T {
+    options.unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        std::process::exit(2);
+    })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1).peekable();
+    let is_root = nix::unistd::getuid().is_root();
+    match args.peek().map(String::as_str) {
+        Some("snapshot") => {
+            let options = parsed(SnapshotOptions::parse(args.skip(1)));
+            take_snapshot(&options.path, is_root && !options.dbus)
+        }
+        Some("diff") => diff_snapshots(&parsed(DiffOptions::parse(args.skip(1)))),
+        _ => {
+            let options = parsed(Options::parse(args));
+            if is_root && !options.dbus {
+                run_local(&options)
+            } else {
+                run_dbus(&options)
+            }
+        }
+    }
+}
+
+/// Reads the page frames in-process, which requires root.
+fn run_local(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut collector = MeminfoCollector::new()?;
+    if let Some(workers) = options.workers {
+        collector.set_workers(workers);
+    }
+    let printer = Printer {
+        json: options.json,
+        categories: options.categories.clone(),
+        page_size: collector.page_size(),
+    };
+    loop {
+        let (stats, _) = collector.refresh()?;
+        printer.print(&stats);
+        match options.watch {
+            Some(interval) => thread::sleep(interval),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Asks meminfo-server for the statistics, subscribing to its updates for
+/// `--watch`.
+fn run_dbus(options: &Options) -> Result<(), Box<dyn Error>> {
+    let connection = zbus::Connection::new_system()?;
+    let collector = MeminfoCollectorProxy::new(&connection)?;
+    let printer = Printer {
+        json: options.json,
+        categories: options.categories.clone(),
+        page_size: procfs::page_size()? as u64,
+    };
+
+    let interval = match options.watch {
+        Some(interval) => interval,
+        None => {
+            let (stats, _) = collector.refresh_physical().map_err(explain)?;
+            printer.print(&stats);
+            return Ok(());
+        }
+    };
+    collector.connect_stats_updated(move |stats| {
+        printer.print(&stats);
+        Ok(())
+    })?;
+    collector
+        .subscribe(interval.as_millis() as u64)
+        .map_err(explain)?;
+    loop {
+        collector.next_signal()?;
+    }
+}
+
+/// Saves a snapshot of the page frame tables to `path`, reading them in-process if
+/// `local`.
+fn take_snapshot(path: &str, local: bool) -> Result<(), Box<dyn Error>> {
+    let file = File::create(path).map_err(|err| format!("cannot create {}: {}", path, err))?;
+    if local {
+        let mut collector = MeminfoCollector::new()?;
+        let metadata = HostMetadata::current(collector.page_size())?;
+        collector.write_snapshot(BufWriter::new(file), &metadata)?;
+    } else {
+        let connection = zbus::Connection::new_system()?;
+        let collector = MeminfoCollectorProxy::new(&connection)?;
+        collector
+            .dump_snapshot(file.as_raw_fd().into())
+            .map_err(explain)?;
+    }
+    Ok(())
+}
+
+fn read_snapshot(path: &str) -> Result<Snapshot, Box<dyn Error>> {
+    let file = File::open(path).map_err(|err| format!("cannot open {}: {}", path, err))?;
+    Ok(Snapshot::read(BufReader::new(file))
+        .map_err(|err| format!("cannot read {}: {}", path, err))?)
+}
+
+fn diff_snapshots(options: &DiffOptions) -> Result<(), Box<dyn Error>> {
+    let before = read_snapshot(&options.before)?;
+    let after = read_snapshot(&options.after)?;
+    if before.metadata.page_size != after.metadata.page_size {
+        return Err("the snapshots differ in their page size".into());
+    }
+    if before.metadata.hostname != after.metadata.hostname {
+        eprintln!(
+            "The snapshots were taken on different hosts, {} and {}.",
+            before.metadata.hostname, after.metadata.hostname
+        );
+    }
+
+    // Only the tables are stored, the compact frame tables are built by a refresh.
+    let mut before_frames = MeminfoCollector::with_source(before.source);
+    before_frames.refresh()?;
+    let mut after_frames = MeminfoCollector::with_source(after.source);
+    after_frames.refresh()?;
+    let mut frame_diff =
+        meminfo_server::diff::diff_frames(before_frames.page_frames(), after_frames.page_frames());
+
+    let total_ranges = frame_diff.changed_ranges.len();
+    frame_diff
+        .changed_ranges
+        .sort_by_key(|range| std::cmp::Reverse(range.end_pfn - range.start_pfn));
+    frame_diff.changed_ranges.truncate(options.ranges);
+    if options.json {
+        println!("{}", serde_json::to_string(&frame_diff)?);
+    } else {
+        print!(
+            "{}",
+            diff::format_diff(&frame_diff, &before.metadata, &after.metadata, total_ranges)
+        );
+    }
+    Ok(())
+}
+
+/// Adds a hint on how to get access to errors due to missing authorization.
+fn explain(err: zbus::Error) -> Box<dyn Error> {
+    match err {
+        zbus::Error::MethodError(ref name, _, _)
+            if name == "org.freedesktop.DBus.Error.AccessDenied"
+                || name == "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired" =>
+        {
+            format!(
+                "{}\nNot authorized to read the statistics, try running as root.",
+                err
+            )
+            .into()
+        }
+        err => err.into(),
+    }
+}
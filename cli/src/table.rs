@@ -0,0 +1,217 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use bytesize::ByteSize;
+use meminfo_common::proc_page::{CompoundOrderStats, PageFrameStats};
+use serde_json::{Map, Value};
+
+/// The groups of statistics that can be selected for printing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Total,
+    Lru,
+    Mmap,
+    Free,
+    Buddy,
+    Compound,
+    Huge,
+    Kernel,
+    Other,
+}
+
+impl Category {
+    pub const ALL: [Category; 9] = [
+        Category::Total,
+        Category::Lru,
+        Category::Mmap,
+        Category::Free,
+        Category::Buddy,
+        Category::Compound,
+        Category::Huge,
+        Category::Kernel,
+        Category::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Total => "total",
+            Category::Lru => "lru",
+            Category::Mmap => "mmap",
+            Category::Free => "free",
+            Category::Buddy => "buddy",
+            Category::Compound => "compound",
+            Category::Huge => "huge",
+            Category::Kernel => "kernel",
+            Category::Other => "other",
+        }
+    }
+
+    /// The fields of [`PageFrameStats`] printed for the category in JSON.
+    fn fields(self) -> &'static [&'static str] {
+        match self {
+            Category::Total => &["frames_in_use", "total_frames"],
+            Category::Lru => &["lru_stats"],
+            Category::Mmap => &["mmaped_stats"],
+            Category::Free => &["free_stats"],
+            Category::Buddy => &["buddy", "buddy_stats"],
+            Category::Compound => &["compound_stats"],
+            Category::Huge => &["huge_stats"],
+            Category::Kernel => &["slab", "pagetable", "reserved", "zero"],
+            Category::Other => &[
+                "shared",
+                "poisoned",
+                "mlocked",
+                "anomalies",
+                "observed_flags",
+            ],
+        }
+    }
+
+    fn rows(self, stats: &PageFrameStats, page_size: u64) -> Vec<(String, Cell)> {
+        use Cell::{Count, Frames};
+        let frames_of = |pages: &[CompoundOrderStats]| {
+            Frames(pages.iter().map(|order| order.bytes).sum::<u64>() / page_size)
+        };
+        let rows = match self {
+            Category::Total => vec![
+                ("present", Frames(stats.total_frames)),
+                ("in use", Frames(stats.frames_in_use)),
+            ],
+            Category::Lru => vec![
+                ("total", Frames(stats.lru_stats.total)),
+                ("active", Frames(stats.lru_stats.active)),
+                ("inactive", Frames(stats.lru_stats.inactive)),
+                ("unevictable", Frames(stats.lru_stats.unevictable)),
+            ],
+            Category::Mmap => vec![
+                ("total", Frames(stats.mmaped_stats.total)),
+                ("anonymous", Frames(stats.mmaped_stats.anon)),
+                ("file", Frames(stats.mmaped_stats.file)),
+            ],
+            Category::Free => vec![
+                ("total", Frames(stats.free_stats.total)),
+                ("never used", Frames(stats.free_stats.noflag)),
+                ("previously used", Frames(stats.free_stats.previously_used)),
+            ],
+            Category::Buddy => {
+                let mut rows = vec![(
+                    "free frames".to_string(),
+                    Frames(stats.buddy_stats.free_frames()),
+                )];
+                for (order, count) in stats.buddy_stats.block_counts().iter().enumerate() {
+                    rows.push((format!("order {} blocks", order), Count(*count)));
+                }
+                return rows;
+            }
+            Category::Compound => vec![
+                ("pages", Count(stats.compound_stats.total)),
+                ("frames", Frames(stats.compound_stats.total_frames)),
+                ("hugetlb", frames_of(&stats.compound_stats.huge_tlb)),
+                ("transparent", frames_of(&stats.compound_stats.transparent)),
+                ("slab", frames_of(&stats.compound_stats.slab)),
+                ("other", frames_of(&stats.compound_stats.other)),
+            ],
+            Category::Huge => vec![
+                ("total", Frames(stats.huge_stats.total_fine_granular)),
+                ("hugetlb", Frames(stats.huge_stats.reserved_fine_granular)),
+                (
+                    "transparent",
+                    Frames(stats.huge_stats.transparent_fine_granular),
+                ),
+                ("hugetlb pages", Count(stats.huge_stats.reserved)),
+                ("transparent pages", Count(stats.huge_stats.transparent)),
+            ],
+            Category::Kernel => vec![
+                ("slab", Frames(stats.slab)),
+                ("page tables", Frames(stats.pagetable)),
+                ("reserved", Frames(stats.reserved)),
+                ("zero page", Frames(stats.zero)),
+            ],
+            Category::Other => vec![
+                ("KSM shared", Frames(stats.shared)),
+                ("hardware poisoned", Frames(stats.poisoned)),
+                ("mlocked", Frames(stats.mlocked)),
+                ("anomalies", Count(stats.anomalies)),
+            ],
+        };
+        rows.into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Category::ALL
+            .iter()
+            .copied()
+            .find(|category| category.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Category::ALL.iter().map(|c| c.name()).collect();
+                format!(
+                    "unknown category '{}', expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A cell of the table, either a number of frames, which is also printed as a size
+/// and a share of all present frames, or a plain count.
+enum Cell {
+    Frames(u64),
+    Count(u64),
+}
+
+/// Formats `stats` as a table with a section per category.
+///
+/// Sizes are printed in binary units, as the kernel does in e.g. `/proc/meminfo`.
+pub fn format_table(stats: &PageFrameStats, categories: &[Category], page_size: u64) -> String {
+    let mut table = format!(
+        "{:<24} {:>12} {:>12} {:>7}\n",
+        "", "frames", "size", "share"
+    );
+    for category in categories {
+        writeln!(table, "{}", category.name()).unwrap();
+        for (name, value) in category.rows(stats, page_size) {
+            match value {
+                Cell::Frames(frames) => {
+                    let share = if stats.total_frames == 0 {
+                        0.0
+                    } else {
+                        frames as f64 * 100.0 / stats.total_frames as f64
+                    };
+                    writeln!(
+                        table,
+                        "  {:<22} {:>12} {:>12} {:>6.2}%",
+                        name,
+                        frames,
+                        ByteSize::b(frames * page_size).to_string_as(true),
+                        share
+                    )
+                }
+                Cell::Count(count) => writeln!(table, "  {:<22} {:>12}", name, count),
+            }
+            .unwrap();
+        }
+    }
+    table
+}
+
+/// Converts the fields of `stats` belonging to `categories` to a JSON object.
+pub fn to_json(stats: &PageFrameStats, categories: &[Category]) -> serde_json::Result<Value> {
+    let mut all = match serde_json::to_value(stats)? {
+        Value::Object(fields) => fields,
+        _ => unreachable!("PageFrameStats is serialized as an object"),
+    };
+    let mut selected = Map::new();
+    for field in categories.iter().flat_map(|category| category.fields()) {
+        if let Some((key, value)) = all.remove_entry(*field) {
+            selected.insert(key, value);
+        }
+    }
+    Ok(Value::Object(selected))
+}
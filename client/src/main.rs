@@ -1,20 +1,21 @@
 mod model;
-mod readinfo;
 mod ui;
 
 use model::Overview;
 use ui::app;
 use ui::dispatch::DispatchLoop;
 
-use std::convert::TryInto;
+use meminfo_common::MeminfoCollectorProxy;
+
 use std::env::args;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+
+/// How often the server sends new page frame statistics.
+const UPDATE_INTERVAL_MS: u64 = 5000;
 
 fn main() {
-    run_server().unwrap();
     let overview: Arc<Overview> = Arc::new(Default::default());
 
     let dispatch_loop = DispatchLoop::new();
@@ -25,60 +26,52 @@ fn main() {
     {
         let sender = sender.clone();
         thread::spawn(move || {
-            use std::collections::HashMap;
-            use zbus::Connection;
-            use zbus_polkit::policykit1::*;
+            let connection = zbus::Connection::new_system().unwrap();
+            let collector = MeminfoCollectorProxy::new(&connection).unwrap();
+            let page_size = procfs::page_size().unwrap() as usize;
 
-            let connection = Connection::new_system().unwrap();
-            let proxy = AuthorityProxy::new(&connection).unwrap();
-            let subject = Subject::new_for_owner(std::process::id(), None, None).unwrap();
-            let result = proxy.check_authorization(
-                &subject,
-                "de.hpi.felix-gohla.pkexec.meminfo",
-                HashMap::new(),
-                CheckAuthorizationFlags::AllowUserInteraction.into(),
-                "",
-            );
-            if let Err(err) = result {
-                eprintln!("Error during authentication: {}", err);
-                std::process::exit(-1);
+            {
+                let sender = sender.clone();
+                collector
+                    .connect_stats_updated(move |stats| {
+                        overview
+                            .ram_total
+                            .store(stats.total_frames as usize * page_size, Ordering::Relaxed);
+                        overview.ram_free.store(
+                            stats.buddy_stats.free_frames() as usize * page_size,
+                            Ordering::Relaxed,
+                        );
+                        *overview.page_frames.lock().unwrap() = stats;
+                        sender.unbounded_send(ui::AppAction::MeminfoUpdate).unwrap();
+                        Ok(())
+                    })
+                    .unwrap();
             }
-            let res = result.unwrap();
 
-            if !res.is_authorized {
-                eprintln!("Was not authorized.");
-                std::process::exit(-1);
+            match collector.subscribe(UPDATE_INTERVAL_MS) {
+                Ok(()) => {}
+                Err(zbus::Error::MethodError(name, _, _))
+                    if name == "org.freedesktop.DBus.Error.AccessDenied"
+                        || name
+                            == "org.freedesktop.DBus.Error.InteractiveAuthorizationRequired" =>
+                {
+                    sender
+                        .unbounded_send(ui::AppAction::ShowNoRootDialog)
+                        .unwrap();
+                    return;
+                }
+                Err(err) => {
+                    eprintln!("Cannot subscribe to page frame statistics: {}", err);
+                    return;
+                }
             }
-
-            println!("metadata for {:?}", users::get_effective_uid());
-
             loop {
-                match readinfo::read_pageflags() {
-                    Ok(_) => {
-                        println!("Can read, have permissions.");
-                    }
-                    Err(err) => {
-                        sender
-                            .unbounded_send(ui::AppAction::ShowNoRootDialog)
-                            .unwrap();
-                        eprintln!("Error {:?}", err);
-                    }
+                if let Err(err) = collector.next_signal() {
+                    eprintln!("Cannot receive page frame statistics: {}", err);
+                    return;
                 }
-                thread::sleep(Duration::from_millis(5000));
-                let info = readinfo::read_meminfo();
-                overview
-                    .ram_total
-                    .store(info.mem_total.try_into().unwrap(), Ordering::Relaxed);
-                overview
-                    .ram_free
-                    .store(info.mem_free.try_into().unwrap(), Ordering::Relaxed);
-                sender.unbounded_send(ui::AppAction::MeminfoUpdate).unwrap();
             }
         });
     }
     application.run(args().collect::<Vec<_>>(), dispatch_loop);
 }
-
-fn run_server() -> Result<usize, String> {
-    Err("not implemented.".to_string())
-}
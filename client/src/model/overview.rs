@@ -1,4 +1,7 @@
 use std::sync::atomic::AtomicUsize;
+use std::sync::Mutex;
+
+use meminfo_common::proc_page::PageFrameStats;
 
 #[derive(Debug, Default)]
 pub struct Overview {
@@ -6,4 +9,6 @@ pub struct Overview {
     pub ram_total: AtomicUsize,
     /// The number of bytes being free and not used by the system.
     pub ram_free: AtomicUsize,
+    /// The page frame statistics last fetched from the server.
+    pub page_frames: Mutex<PageFrameStats>,
 }
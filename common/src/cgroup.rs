@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+use crate::proc_page::PageCategoryStats;
+
+/// The frames charged to a single memory cgroup.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct CgroupStats {
+    /// The inode number of the cgroup's directory. `0` collects the frames that
+    /// are not charged to any cgroup.
+    pub inode: u64,
+    /// The path of the cgroup relative to the hierarchy's root, e.g. `/system.slice`.
+    /// Empty if the inode could not be resolved, e.g. as the cgroup has been removed
+    /// while some of its frames are still around.
+    pub path: String,
+    pub stats: PageCategoryStats,
+}
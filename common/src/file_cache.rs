@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+use crate::proc_page::PageFlags;
+
+/// The pages of a file held in the page cache.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct FileCacheStats {
+    pub path: String,
+    /// The bytes of the file held in the page cache.
+    pub resident: u64,
+    /// The number of resident pages with data not yet written back.
+    pub dirty: u64,
+    /// The number of resident pages being written back.
+    pub writeback: u64,
+}
+
+impl FileCacheStats {
+    /// Accounts a resident page with the given flags.
+    pub fn account(&mut self, flags: PageFlags, page_size: u64) {
+        self.resident += page_size;
+        if flags.contains(PageFlags::DIRTY) {
+            self.dirty += 1;
+        }
+        if flags.contains(PageFlags::WRITEBACK) {
+            self.writeback += 1;
+        }
+    }
+}
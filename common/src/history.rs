@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+use crate::proc_page::PageFrameStats;
+
+/// The statistics of a refresh, as recorded in the server's history.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct HistorySample {
+    /// When the refresh happened, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub stats: PageFrameStats,
+}
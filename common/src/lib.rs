@@ -0,0 +1,23 @@
+//! The types exchanged between the meminfo server and its clients over D-Bus,
+//! together with a proxy for the server's interface.
+
+pub mod cgroup;
+pub mod file_cache;
+pub mod history;
+pub mod proc_page;
+pub mod process;
+mod proxy;
+pub mod reverse_map;
+pub mod set_size;
+pub mod working_set;
+
+pub use proxy::MeminfoCollectorProxy;
+
+/// The well-known bus name the server requests on the system bus.
+pub const SERVICE_NAME: &str = "de.hpi.felixgohla.meminfo";
+
+/// The object path the server exports the collector at.
+pub const OBJECT_PATH: &str = "/de/hpi/felixgohla/meminfo";
+
+/// The interface of the collector.
+pub const INTERFACE_NAME: &str = "de.hpi.felixgohla.meminfo.meminfo_collector";
@@ -0,0 +1,566 @@
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use zvariant::derive::Type;
+
+bitflags! {
+    /// Describes the status of a page frame.
+    ///
+    /// Also see documentation at: <https://www.kernel.org/doc/Documentation/vm/pagemap.txt>
+    pub struct PageFlags: u64 {
+        /// page is being locked for exclusive access, eg. by undergoing read/write IO
+        const LOCKED        = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_00000001;
+        /// **IO related:** IO error occurred
+        const ERROR         = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_00000010;
+        /// **LRU related:** page has been referenced since last LRU list enqueue/requeue
+        const REFERENCED    = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_00000100;
+        /// **IO related:** page has up-to-date data
+        /// ie. for file backed page: (in-memory data revision >= on-disk one)
+        const UPTODATE      = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_00001000;
+        /// **IO related:** page has been written to, hence contains new data
+        /// ie. for file backed page: (in-memory data revision >  on-disk one)
+        const DIRTY         = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_00010000;
+        /// **LRU related:** page is in one of the LRU lists
+        const LRU           = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_00100000;
+        /// **LRU related:** page is in the active LRU list
+        const ACTIVE        = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_01000000;
+        /// page is managed by the SLAB/SLOB/SLUB/SLQB kernel memory allocator
+        /// When compound page is used, SLUB/SLQB will only set this flag on the head
+        /// page; SLOB will not flag it at all.
+        const SLAB          = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000000_10000000;
+        /// **IO related:** page is being synced to disk
+        const WRITEBACK     = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000001_00000000;
+        /// **LRU related:** page will be reclaimed soon after its pageout IO completed
+        const RECLAIM       = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000010_00000000;
+        /// a free memory block managed by the buddy system allocator
+        /// The buddy system organizes free memory in blocks of various orders.
+        /// An order N block has 2^N physically contiguous pages, with the BUDDY flag
+        /// set for and _only_ for the first page. Newer kernels report it for every
+        /// page of a free block nevertheless.
+        const BUDDY         = 0b00000000_00000000_00000000_00000000_00000000_00000000_00000100_00000000;
+        /// **LRU related:** a memory mapped page
+        const MMAP          = 0b00000000_00000000_00000000_00000000_00000000_00000000_00001000_00000000;
+        /// **LRU related:** a memory mapped page that is not part of a file
+        const ANON          = 0b00000000_00000000_00000000_00000000_00000000_00000000_00010000_00000000;
+        /// **LRU related:** page is mapped to swap space, ie. has an associated swap entry
+        const SWAPCACHE     = 0b00000000_00000000_00000000_00000000_00000000_00000000_00100000_00000000;
+        /// **LRU related:** page is backed by swap/RAM
+        const SWAPBACKEND   = 0b00000000_00000000_00000000_00000000_00000000_00000000_01000000_00000000;
+        /// A compound page with order N consists of 2^N physically contiguous pages.
+        /// A compound page with order 2 takes the form of "HTTT", where H donates its
+        /// head page and T donates its tail page(s).  The major consumers of compound
+        /// pages are hugeTLB pages (Documentation/vm/hugetlbpage.txt), the SLUB etc.
+        /// memory allocators and various device drivers. However in this interface,
+        /// only huge/giga pages are made visible to end users.
+        const COMPOUND_HEAD = 0b00000000_00000000_00000000_00000000_00000000_00000000_10000000_00000000;
+        /// A compound page with order N consists of 2^N physically contiguous pages.
+        /// A compound page with order 2 takes the form of "HTTT", where H donates its
+        /// head page and T donates its tail page(s).  The major consumers of compound
+        /// pages are hugeTLB pages (Documentation/vm/hugetlbpage.txt), the SLUB etc.
+        /// memory allocators and various device drivers. However in this interface,
+        /// only huge/giga pages are made visible to end users.
+        const COMPOUND_TAIL = 0b00000000_00000000_00000000_00000000_00000000_00000001_00000000_00000000;
+        /// this is an integral part of a HugeTLB page
+        const HUGE          = 0b00000000_00000000_00000000_00000000_00000000_00000010_00000000_00000000;
+        /// **LRU related:** page is in the unevictable (non-)LRU list
+        /// It is somehow pinned and not a candidate for LRU page reclaims,
+        /// eg. ramfs pages, shmctl(SHM_LOCK) and mlock() memory segments
+        const UNEVICTABLE   = 0b00000000_00000000_00000000_00000000_00000000_00000100_00000000_00000000;
+        /// hardware detected memory corruption on this page: don't touch the data!
+        const HWPOISON      = 0b00000000_00000000_00000000_00000000_00000000_00001000_00000000_00000000;
+        /// no page frame exists at the requested address
+        const NOPAGE        = 0b00000000_00000000_00000000_00000000_00000000_00010000_00000000_00000000;
+        /// identical memory pages dynamically shared between one or more processes
+        const KSM           = 0b00000000_00000000_00000000_00000000_00000000_00100000_00000000_00000000;
+        /// contiguous pages which construct transparent hugepages
+        const THP           = 0b00000000_00000000_00000000_00000000_00000000_01000000_00000000_00000000;
+        /// tbd
+        const OFFLINE       = 0b00000000_00000000_00000000_00000000_00000000_10000000_00000000_00000000;
+        /// zero page for pfn_zero or huge_zero page
+        const ZERO_PAGE     = 0b00000000_00000000_00000000_00000000_00000001_00000000_00000000_00000000;
+        /// page has not been accessed since it was marked idle (see
+        /// Documentation/vm/idle_page_tracking.txt). Note that this flag may be
+        /// stale in case the page was accessed via a PTE. To make sure the flag
+        /// is up-to-date one has to read /sys/kernel/mm/page_idle/bitmap first.
+        const IDLE          = 0b00000000_00000000_00000000_00000000_00000010_00000000_00000000_00000000;
+        /// contains paging structures
+        const PAGETABLE     = 0b00000000_00000000_00000000_00000000_00000100_00000000_00000000_00000000;
+
+        // The following flags are only meant for kernel hacking and are subject to change.
+        /// **kernel hacking:** page is reserved, eg. kernel text or memory set aside at boot
+        const RESERVED      = 0b00000000_00000000_00000000_00000001_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** page is locked in memory by mlock()
+        const MLOCKED       = 0b00000000_00000000_00000000_00000010_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** page has blocks allocated on disk
+        const MAPPEDTODISK  = 0b00000000_00000000_00000000_00000100_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** page has private data of its owner, eg. buffer heads
+        const PRIVATE       = 0b00000000_00000000_00000000_00001000_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** page has further private data, eg. for filesystem caching
+        const PRIVATE_2     = 0b00000000_00000000_00000000_00010000_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** flag for use by the owner of the page
+        const OWNER_PRIVATE = 0b00000000_00000000_00000000_00100000_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** architecture specific flag
+        const ARCH          = 0b00000000_00000000_00000000_01000000_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** page is mapped uncached
+        const UNCACHED      = 0b00000000_00000000_00000000_10000000_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** page has been written to since the soft-dirty bits were cleared
+        const SOFTDIRTY     = 0b00000000_00000000_00000001_00000000_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** second architecture specific flag
+        const ARCH_2        = 0b00000000_00000000_00000010_00000000_00000000_00000000_00000000_00000000;
+        /// **kernel hacking:** third architecture specific flag
+        const ARCH_3        = 0b00000000_00000000_00000100_00000000_00000000_00000000_00000000_00000000;
+   }
+}
+
+impl PageFlags {
+    /// Names the bits set in `bits`. Bits unknown to [`PageFlags`] are named by their
+    /// position, e.g. `BIT_43`.
+    pub fn names(bits: u64) -> Vec<String> {
+        (0..64)
+            .map(|bit| 1u64 << bit)
+            .filter(|flag| bits & flag != 0)
+            .map(|flag| match PageFlags::from_bits(flag) {
+                Some(known) => format!("{:?}", known),
+                None => format!("BIT_{}", flag.trailing_zeros()),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Represents the state of a physical page frame.
+pub struct PageFrame {
+    /// The number of times the frame is used.
+    pub reference_count: u64,
+    /// The state of the page frame.
+    pub flags: PageFlags,
+}
+
+/// The category a page frame is counted in by [`PageFrameStats`], excluding all
+/// others. Frames matching several categories count in the first listed here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameCategory {
+    Poisoned,
+    Ksm,
+    Buddy,
+    Slab,
+    PageTable,
+    Anon,
+    File,
+    ZeroPage,
+    Reserved,
+    /// Unused frames without any flags set.
+    Free,
+    /// Unused frames still carrying flags of their last use.
+    PreviouslyUsed,
+}
+
+impl FrameCategory {
+    pub fn of(flags: PageFlags) -> Self {
+        if flags.contains(PageFlags::HWPOISON) {
+            FrameCategory::Poisoned
+        } else if flags.contains(PageFlags::KSM) {
+            FrameCategory::Ksm
+        } else if flags.contains(PageFlags::BUDDY) {
+            FrameCategory::Buddy
+        } else if flags.contains(PageFlags::SLAB) {
+            FrameCategory::Slab
+        } else if flags.contains(PageFlags::PAGETABLE) {
+            FrameCategory::PageTable
+        } else if flags.contains(PageFlags::MMAP) {
+            if flags.contains(PageFlags::ANON) {
+                FrameCategory::Anon
+            } else {
+                FrameCategory::File
+            }
+        } else if flags.contains(PageFlags::ZERO_PAGE) {
+            FrameCategory::ZeroPage
+        } else if flags.contains(PageFlags::RESERVED) {
+            FrameCategory::Reserved
+        } else if flags.is_empty() {
+            FrameCategory::Free
+        } else {
+            FrameCategory::PreviouslyUsed
+        }
+    }
+}
+
+/// The LRU list a page frame is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LruList {
+    Inactive,
+    Active,
+    Unevictable,
+}
+
+impl LruList {
+    /// The list of a frame with `flags`, `None` if it is on none.
+    ///
+    /// Frames flagged both active and unevictable violate
+    /// [`Invariant::ActiveIsEvictable`] and are considered active.
+    pub fn of(flags: PageFlags) -> Option<Self> {
+        if !flags.contains(PageFlags::LRU) {
+            None
+        } else if flags.contains(PageFlags::ACTIVE) {
+            Some(LruList::Active)
+        } else if flags.contains(PageFlags::UNEVICTABLE) {
+            Some(LruList::Unevictable)
+        } else {
+            Some(LruList::Inactive)
+        }
+    }
+}
+
+/// An invariant about page frames that the kernel data is expected to uphold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr, Type)]
+#[repr(u32)]
+pub enum Invariant {
+    /// Pages in the active LRU list are evictable.
+    ActiveIsEvictable = 0,
+    /// KSM pages are shared by at least two users.
+    KsmIsShared = 1,
+    /// Pages without any owner are not referenced.
+    UnusedIsUnreferenced = 2,
+    /// Compound tail pages directly follow their head page or another tail page.
+    TailFollowsHead = 3,
+    /// Compound pages consist of a power of two frames.
+    CompoundIsPowerOfTwo = 4,
+}
+
+/// A page frame whose state violated an [`Invariant`] while being classified.
+///
+/// As the kernel tables are read while the system keeps running, frames may change
+/// between reading their flags and their reference count.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct Anomaly {
+    pub pfn: u64,
+    /// The raw flags of the frame.
+    pub flags: u64,
+    pub reference_count: u64,
+    pub invariant: Invariant,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct LRUPageFrameStats {
+    pub total: u64,
+    pub active: u64,
+    pub inactive: u64,
+    pub unevictable: u64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct MmapFrameStats {
+    pub total: u64,
+    pub anon: u64,
+    pub file: u64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct FreeFramesStats {
+    pub total: u64,
+    pub noflag: u64,
+    /// Frames that are not referenced but still in the LRU lists and
+    /// therefore not available in some allocator.
+    pub previously_used: u64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct HugeFramesStats {
+    pub total: u64,
+    pub total_fine_granular: u64,
+    pub reserved: u64,
+    pub reserved_fine_granular: u64,
+    pub transparent: u64,
+    pub transparent_fine_granular: u64,
+}
+
+/// Frames of a subset of the system, e.g. a cgroup or a process, broken down by their use.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct PageCategoryStats {
+    pub total: u64,
+    /// Anonymous frames, ie. not backed by a file.
+    pub anon: u64,
+    /// Frames of the page cache, mapped or not.
+    pub file: u64,
+    pub slab: u64,
+    pub lru_active: u64,
+    pub lru_inactive: u64,
+    /// Frames that are part of a HugeTLB or transparent huge page.
+    pub huge: u64,
+}
+
+/// Compound pages of a single order.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct CompoundOrderStats {
+    /// A compound page of order N consists of 2^N frames.
+    pub order: u32,
+    /// The number of compound pages.
+    pub count: u64,
+    /// The number of bytes in these compound pages.
+    pub bytes: u64,
+}
+
+/// Compound pages grouped by the kind of their owner. The owners' lists are sorted
+/// by order and only contain orders that occurred.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct CompoundFramesStats {
+    /// The number of compound pages.
+    pub total: u64,
+    /// The number of frames that are part of a compound page.
+    pub total_frames: u64,
+    pub huge_tlb: Vec<CompoundOrderStats>,
+    pub transparent: Vec<CompoundOrderStats>,
+    pub slab: Vec<CompoundOrderStats>,
+    /// Compound pages of drivers and other kernel users.
+    pub other: Vec<CompoundOrderStats>,
+}
+
+/// Free blocks of the buddy allocator, indexed by their order.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct BuddyStats {
+    /// Free blocks inferred from the page flags.
+    pub blocks: Vec<u64>,
+    /// Free blocks as reported by the kernel in `/proc/buddyinfo`, summed over all
+    /// nodes and zones. Empty if the source does not provide it.
+    pub buddyinfo: Vec<u64>,
+    /// The fraction of free memory that cannot serve an allocation of the order,
+    /// from 0 (no fragmentation) to 1 (no block of the order or above is free).
+    ///
+    /// Based on `buddyinfo` if available, otherwise on `blocks`.
+    pub unusable_index: Vec<f64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+/// Statistics about physical page frames in the system.
+///
+/// Sent over D-Bus as a structure whose members follow the field order below.
+pub struct PageFrameStats {
+    pub lru_stats: LRUPageFrameStats,
+
+    pub mmaped_stats: MmapFrameStats,
+
+    pub free_stats: FreeFramesStats,
+
+    /// Frames that are shared in KSM.
+    pub shared: u64,
+
+    /// Frames with faulty hardware.
+    pub poisoned: u64,
+
+    /// Frames flagged as part of the buddy allocator. Older kernels flag only the
+    /// first frame of a free block, so see [`BuddyStats::free_frames`] for the free
+    /// memory.
+    pub buddy: u64,
+    pub buddy_stats: BuddyStats,
+
+    pub slab: u64,
+    pub zero: u64,
+
+    pub compound_stats: CompoundFramesStats,
+
+    pub huge_stats: HugeFramesStats,
+
+    pub pagetable: u64,
+
+    /// Frames reserved by the kernel, eg. for its text or boot time allocations.
+    pub reserved: u64,
+
+    /// Frames locked in memory by mlock(). These are also counted in their category.
+    pub mlocked: u64,
+
+    pub frames_in_use: u64,
+    pub total_frames: u64,
+
+    /// The number of frames that violated an [`Invariant`].
+    pub anomalies: u64,
+
+    /// All flag bits that were set on at least one frame, including bits unknown to
+    /// [`PageFlags`]. Tells which flags the kernel actually emits.
+    pub observed_flags: u64,
+}
+
+impl LRUPageFrameStats {
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.active += other.active;
+        self.inactive += other.inactive;
+        self.unevictable += other.unevictable;
+    }
+}
+
+impl MmapFrameStats {
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.anon += other.anon;
+        self.file += other.file;
+    }
+}
+
+impl FreeFramesStats {
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.noflag += other.noflag;
+        self.previously_used += other.previously_used;
+    }
+}
+
+impl HugeFramesStats {
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.total_fine_granular += other.total_fine_granular;
+        self.reserved += other.reserved;
+        self.reserved_fine_granular += other.reserved_fine_granular;
+        self.transparent += other.transparent;
+        self.transparent_fine_granular += other.transparent_fine_granular;
+    }
+}
+
+impl PageCategoryStats {
+    /// Accounts a single frame.
+    pub fn account(&mut self, flags: PageFlags) {
+        self.total += 1;
+        if flags.contains(PageFlags::ANON) {
+            self.anon += 1;
+        } else if flags.intersects(PageFlags::MMAP | PageFlags::LRU) {
+            self.file += 1;
+        }
+        if flags.contains(PageFlags::SLAB) {
+            self.slab += 1;
+        }
+        if flags.contains(PageFlags::LRU) {
+            if flags.contains(PageFlags::ACTIVE) {
+                self.lru_active += 1;
+            } else {
+                self.lru_inactive += 1;
+            }
+        }
+        if flags.intersects(PageFlags::HUGE | PageFlags::THP) {
+            self.huge += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.anon += other.anon;
+        self.file += other.file;
+        self.slab += other.slab;
+        self.lru_active += other.lru_active;
+        self.lru_inactive += other.lru_inactive;
+        self.huge += other.huge;
+    }
+}
+
+impl CompoundOrderStats {
+    /// Adds `count` compound pages of `order` to a list sorted by order.
+    pub fn account(by_order: &mut Vec<CompoundOrderStats>, order: u32, count: u64, bytes: u64) {
+        let index = match by_order.binary_search_by_key(&order, |stats| stats.order) {
+            Ok(index) => index,
+            Err(index) => {
+                by_order.insert(
+                    index,
+                    CompoundOrderStats {
+                        order,
+                        ..Default::default()
+                    },
+                );
+                index
+            }
+        };
+        by_order[index].count += count;
+        by_order[index].bytes += bytes;
+    }
+}
+
+impl CompoundFramesStats {
+    pub fn merge(&mut self, other: &Self) {
+        self.total += other.total;
+        self.total_frames += other.total_frames;
+        let lists = [
+            (&mut self.huge_tlb, &other.huge_tlb),
+            (&mut self.transparent, &other.transparent),
+            (&mut self.slab, &other.slab),
+            (&mut self.other, &other.other),
+        ];
+        for (by_order, other_by_order) in lists {
+            for stats in other_by_order {
+                CompoundOrderStats::account(by_order, stats.order, stats.count, stats.bytes);
+            }
+        }
+    }
+}
+
+impl BuddyStats {
+    pub fn merge(&mut self, other: &Self) {
+        if self.blocks.len() < other.blocks.len() {
+            self.blocks.resize(other.blocks.len(), 0);
+        }
+        for (blocks, other_blocks) in self.blocks.iter_mut().zip(&other.blocks) {
+            *blocks += other_blocks;
+        }
+    }
+
+    /// The free blocks per order, from `buddyinfo` if available, otherwise from
+    /// `blocks`.
+    pub fn block_counts(&self) -> &[u64] {
+        if self.buddyinfo.is_empty() {
+            &self.blocks
+        } else {
+            &self.buddyinfo
+        }
+    }
+
+    /// The number of free frames in all blocks of [`block_counts`](Self::block_counts).
+    pub fn free_frames(&self) -> u64 {
+        self.block_counts()
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum()
+    }
+
+    /// Computes the unusable free space index for every order.
+    pub fn update_unusable_index(&mut self) {
+        let free_frames = self.free_frames();
+        let mut usable_frames = free_frames;
+        self.unusable_index = self
+            .block_counts()
+            .iter()
+            .enumerate()
+            .map(|(order, count)| {
+                let index = if free_frames == 0 {
+                    1.0
+                } else {
+                    (free_frames - usable_frames) as f64 / free_frames as f64
+                };
+                usable_frames -= count << order;
+                index
+            })
+            .collect();
+    }
+}
+
+impl PageFrameStats {
+    /// Adds the statistics of another, disjoint set of frames.
+    pub fn merge(&mut self, other: &Self) {
+        self.lru_stats.merge(&other.lru_stats);
+        self.mmaped_stats.merge(&other.mmaped_stats);
+        self.free_stats.merge(&other.free_stats);
+        self.shared += other.shared;
+        self.poisoned += other.poisoned;
+        self.buddy += other.buddy;
+        self.buddy_stats.merge(&other.buddy_stats);
+        self.slab += other.slab;
+        self.zero += other.zero;
+        self.compound_stats.merge(&other.compound_stats);
+        self.huge_stats.merge(&other.huge_stats);
+        self.pagetable += other.pagetable;
+        self.reserved += other.reserved;
+        self.mlocked += other.mlocked;
+        self.frames_in_use += other.frames_in_use;
+        self.total_frames += other.total_frames;
+        self.anomalies += other.anomalies;
+        self.observed_flags |= other.observed_flags;
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+use crate::proc_page::PageCategoryStats;
+
+/// The pages of a virtual address range, joined with the frames backing them.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct MappedPagesStats {
+    /// Pages backed by a frame.
+    pub resident: u64,
+    /// Pages moved to swap.
+    pub swapped: u64,
+    /// Resident pages whose frame is mapped once, ie. only by this mapping.
+    pub exclusive: u64,
+    /// Resident pages whose frame is mapped more than once.
+    pub shared: u64,
+    /// Resident pages whose frame is unknown to the page frame table, as it was not
+    /// present during the last refresh or PFNs are hidden from the server.
+    pub unknown: u64,
+    /// The resident pages with a known frame, broken down by the frames' flags.
+    pub frames: PageCategoryStats,
+}
+
+impl MappedPagesStats {
+    pub fn merge(&mut self, other: &Self) {
+        self.resident += other.resident;
+        self.swapped += other.swapped;
+        self.exclusive += other.exclusive;
+        self.shared += other.shared;
+        self.unknown += other.unknown;
+        self.frames.merge(&other.frames);
+    }
+}
+
+/// A virtual memory area of a process, as listed in `/proc/PID/maps`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct VmaStats {
+    pub start: u64,
+    pub end: u64,
+    /// The permissions, e.g. `r-xp`.
+    pub perms: String,
+    /// The offset into the mapped file.
+    pub offset: u64,
+    /// The mapped file or a pseudo-path like `[heap]`. Empty for anonymous mappings.
+    pub path: String,
+    pub pages: MappedPagesStats,
+}
+
+/// The pages mapped by a process.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct ProcessStats {
+    pub pid: u32,
+    /// The name of the process' executable, as in `/proc/PID/comm`.
+    pub comm: String,
+    /// The sum over all VMAs.
+    pub pages: MappedPagesStats,
+    pub vmas: Vec<VmaStats>,
+}
@@ -0,0 +1,72 @@
+use zbus::dbus_proxy;
+
+use crate::cgroup::CgroupStats;
+use crate::file_cache::FileCacheStats;
+use crate::history::HistorySample;
+use crate::proc_page::{Anomaly, PageFrameStats};
+use crate::process::ProcessStats;
+use crate::reverse_map::FrameOwners;
+use crate::set_size::{CgroupSetSizes, ProcessSetSizes};
+use crate::working_set::WorkingSetStats;
+
+/// The interface of the server's collector. See the server for the documentation of
+/// each method.
+#[dbus_proxy(
+    interface = "de.hpi.felixgohla.meminfo.meminfo_collector",
+    default_service = "de.hpi.felixgohla.meminfo",
+    default_path = "/de/hpi/felixgohla/meminfo"
+)]
+trait MeminfoCollector {
+    fn subscribe(&self, interval_ms: u64) -> zbus::Result<()>;
+
+    fn unsubscribe(&self) -> zbus::Result<()>;
+
+    fn emitted_flags(&self) -> zbus::Result<Vec<String>>;
+
+    fn cgroup_breakdown(&self) -> zbus::Result<Vec<CgroupStats>>;
+
+    fn process_map(&self, pid: u32) -> zbus::Result<ProcessStats>;
+
+    fn process_totals(&self) -> zbus::Result<Vec<ProcessStats>>;
+
+    fn frame_owners(&self, start_pfn: u64, end_pfn: u64) -> zbus::Result<Vec<FrameOwners>>;
+
+    fn address_owners(&self, start: u64, end: u64) -> zbus::Result<Vec<FrameOwners>>;
+
+    fn set_sizes(&self) -> zbus::Result<(Vec<ProcessSetSizes>, Vec<CgroupSetSizes>)>;
+
+    fn mapped_file_cache(&self) -> zbus::Result<Vec<FileCacheStats>>;
+
+    fn probe_file_cache(&self, paths: &[&str]) -> zbus::Result<Vec<FileCacheStats>>;
+
+    fn dump_snapshot(&self, fd: zvariant::Fd) -> zbus::Result<()>;
+
+    fn history(&self, start_ms: u64, end_ms: u64) -> zbus::Result<Vec<HistorySample>>;
+
+    fn estimate_working_set(&self, interval_ms: u64) -> zbus::Result<WorkingSetStats>;
+}
+
+impl MeminfoCollectorProxy<'_> {
+    /// Re-reads all physical page frames and returns statistics about them,
+    /// together with the frames that violated an invariant while being read.
+    ///
+    /// Implemented by hand, as zbus mistakes the reply's out arguments for a single
+    /// structure when the first of them is a structure itself.
+    pub fn refresh_physical(&self) -> zbus::Result<(PageFrameStats, Vec<Anomaly>)> {
+        let reply = self.call_method("RefreshPhysical", &())?;
+        Ok(reply.body_unchecked()?)
+    }
+
+    /// Registers `handler` for the `StatsUpdated` signals sent after `subscribe`.
+    /// Signals are received by `next_signal`.
+    ///
+    /// Implemented by hand for the same reason as `refresh_physical`.
+    pub fn connect_stats_updated<H>(&self, mut handler: H) -> zbus::fdo::Result<()>
+    where
+        H: FnMut(PageFrameStats) -> zbus::Result<()> + Send + 'static,
+    {
+        self.connect_signal("StatsUpdated", move |message| {
+            handler(message.body_unchecked()?)
+        })
+    }
+}
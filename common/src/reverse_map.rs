@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+/// A page of a process mapping a frame.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct FrameOwner {
+    pub pid: u32,
+    /// The name of the process' executable, as in `/proc/PID/comm`.
+    pub comm: String,
+    /// The start of the VMA containing the page.
+    pub vma_start: u64,
+    /// The end of the VMA containing the page.
+    pub vma_end: u64,
+    /// The mapped file or a pseudo-path like `[heap]`. Empty for anonymous mappings.
+    pub path: String,
+    /// The virtual address of the page.
+    pub address: u64,
+}
+
+/// A present page frame together with the pages mapping it.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct FrameOwners {
+    pub pfn: u64,
+    /// The raw flags of the frame.
+    pub flags: u64,
+    /// The exact reference count, not saturated as in the page frame table.
+    pub reference_count: u64,
+    /// Empty if no process maps the frame, e.g. as it belongs to the kernel.
+    pub owners: Vec<FrameOwner>,
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+/// The memory used by a process or a group of processes, in bytes.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct SetSizes {
+    /// The resident set size: all resident pages.
+    pub rss: u64,
+    /// The proportional set size: each resident page divided by the number of times
+    /// its frame is mapped.
+    pub pss: u64,
+    /// The unique set size: resident pages whose frame is mapped only once.
+    pub uss: u64,
+}
+
+impl SetSizes {
+    pub fn merge(&mut self, other: &Self) {
+        self.rss += other.rss;
+        self.pss += other.pss;
+        self.uss += other.uss;
+    }
+}
+
+/// The set sizes of a single process.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct ProcessSetSizes {
+    pub pid: u32,
+    /// The name of the process' executable, as in `/proc/PID/comm`.
+    pub comm: String,
+    /// The path of the memory cgroup the process belongs to.
+    pub cgroup: String,
+    /// Computed from the process' pagemap and the frames' reference counts.
+    pub computed: SetSizes,
+    /// As reported by the kernel in `/proc/PID/smaps_rollup`. All zero if the kernel
+    /// does not provide it.
+    pub kernel: SetSizes,
+    /// Whether `computed` and `kernel` deviate by more than expected from the process
+    /// running while being inspected.
+    pub divergent: bool,
+}
+
+/// The set sizes summed over the processes of a memory cgroup.
+///
+/// Only `pss` adds up to the memory used by the cgroup's processes; `rss` counts
+/// frames shared between them repeatedly.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct CgroupSetSizes {
+    /// The path of the cgroup relative to the hierarchy's root, e.g. `/system.slice`.
+    pub path: String,
+    pub processes: u32,
+    pub computed: SetSizes,
+    pub kernel: SetSizes,
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+
+use crate::proc_page::PageCategoryStats;
+
+/// Pages of a process or cgroup, split by whether they were accessed.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct IdleCounts {
+    /// Pages accessed during the interval.
+    pub accessed: u64,
+    /// Pages left idle during the interval.
+    pub idle: u64,
+}
+
+impl IdleCounts {
+    pub fn account(&mut self, idle: bool) {
+        if idle {
+            self.idle += 1;
+        } else {
+            self.accessed += 1;
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct ProcessWorkingSet {
+    pub pid: u32,
+    /// The name of the process' executable, as in `/proc/PID/comm`.
+    pub comm: String,
+    pub pages: IdleCounts,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct CgroupWorkingSet {
+    /// The inode number of the cgroup's directory.
+    pub inode: u64,
+    /// The path of the cgroup relative to the hierarchy's root. Empty if the inode
+    /// could not be resolved.
+    pub path: String,
+    pub pages: IdleCounts,
+}
+
+/// The user pages accessed during an interval, ie. the working set, and the pages
+/// left idle, ie. cold memory.
+///
+/// Only frames on the LRU lists are tracked by the kernel; other frames are left out.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct WorkingSetStats {
+    /// The time between marking the pages idle and checking them, in milliseconds.
+    pub interval_ms: u64,
+    pub accessed: PageCategoryStats,
+    pub idle: PageCategoryStats,
+    /// Sorted by the number of idle pages, largest first.
+    pub processes: Vec<ProcessWorkingSet>,
+    /// Sorted by the number of idle pages, largest first. Empty if the source does not
+    /// know about cgroups.
+    pub cgroups: Vec<CgroupWorkingSet>,
+}
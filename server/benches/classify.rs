@@ -0,0 +1,86 @@
+//! Compares sequential and parallel page classification on synthetic page data.
+//!
+//! Run with `cargo bench -p meminfo-server --bench classify [FRAMES]`.
+
+use std::time::{Duration, Instant};
+
+use meminfo_server::proc_page::PageFlags;
+use meminfo_server::source::MemorySource;
+use meminfo_server::MeminfoCollector;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+
+/// 16 GiB worth of 4 KiB pages.
+const DEFAULT_FRAMES: usize = 1 << 22;
+const ITERATIONS: u32 = 5;
+
+/// Generates tables that roughly resemble a busy machine: mostly mapped and free
+/// frames, some kernel allocations and a hole in the physical address space.
+fn synthetic_source(frames: usize) -> MemorySource {
+    let mut rng = SmallRng::seed_from_u64(0x6d656d696e666f);
+    let hole = frames / 8..frames / 8 + frames / 64;
+    let mut counts = Vec::with_capacity(frames);
+    let mut flags = Vec::with_capacity(frames);
+    for pfn in 0..frames {
+        let (frame_flags, count) = if hole.contains(&pfn) {
+            (PageFlags::NOPAGE, 0)
+        } else {
+            match rng.gen_range(0..100) {
+                0..=29 => (PageFlags::empty(), 0),
+                30..=34 => (PageFlags::BUDDY, 0),
+                35..=64 => (
+                    PageFlags::MMAP | PageFlags::ANON | PageFlags::LRU | PageFlags::ACTIVE,
+                    rng.gen_range(1..4),
+                ),
+                65..=89 => (
+                    PageFlags::MMAP | PageFlags::LRU | PageFlags::UPTODATE,
+                    rng.gen_range(1..16),
+                ),
+                90..=96 => (PageFlags::SLAB, 1),
+                _ => (PageFlags::PAGETABLE, 1),
+            }
+        };
+        flags.push(frame_flags.bits());
+        counts.push(count);
+    }
+    MemorySource::new(counts, flags, 4096).expect("tables have the same length")
+}
+
+fn bench(collector: &mut MeminfoCollector<MemorySource>) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        collector
+            .refresh()
+            .expect("synthetic frames can be classified");
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let frames = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_FRAMES);
+    let source = synthetic_source(frames);
+    let workers = std::thread::available_parallelism().map_or(1, usize::from);
+
+    let mut sequential = MeminfoCollector::with_source(source.clone());
+    sequential.set_workers(1);
+    let mut parallel = MeminfoCollector::with_source(source);
+    parallel.set_workers(workers);
+    assert_eq!(
+        sequential.refresh().unwrap(),
+        parallel.refresh().unwrap(),
+        "sequential and parallel classification disagree"
+    );
+
+    let sequential_time = bench(&mut sequential);
+    let parallel_time = bench(&mut parallel);
+    println!("frames:                 {}", frames);
+    println!("sequential:             {:?}", sequential_time);
+    println!("parallel ({:>2} workers): {:?}", workers, parallel_time);
+    println!(
+        "speedup:                {:.2}x",
+        sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+    );
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use zbus::{fdo, Connection, MessageFlags, MessageHeader};
+use zbus_polkit::policykit1::{AuthorityProxy, CheckAuthorizationFlags, Subject};
+
+/// The polkit actions guarding the methods of the collector, as declared in
+/// `server/dist/de.hpi.felixgohla.meminfo.policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Reading statistics about the whole system.
+    ReadStats,
+    /// Reading which pages and files individual processes map, probing files or
+    /// dumping the raw page frame tables.
+    InspectProcesses,
+    /// Changing the state of the kernel's memory management, e.g. page idle bits.
+    Tune,
+}
+
+impl Action {
+    pub fn id(self) -> &'static str {
+        match self {
+            Action::ReadStats => "de.hpi.felixgohla.meminfo.read-stats",
+            Action::InspectProcesses => "de.hpi.felixgohla.meminfo.inspect-processes",
+            Action::Tune => "de.hpi.felixgohla.meminfo.tune",
+        }
+    }
+}
+
+/// Checks the callers of D-Bus methods against polkit.
+pub struct Authority {
+    proxy: AuthorityProxy<'static>,
+}
+
+impl Authority {
+    /// Connects to polkit on the system bus.
+    ///
+    /// This opens a connection of its own, so that the connection serving requests
+    /// is never blocked on a call to polkit.
+    pub fn system() -> zbus::Result<Self> {
+        let connection = Connection::new_system()?;
+        Ok(Self {
+            proxy: AuthorityProxy::new(&connection)?,
+        })
+    }
+
+    /// Checks whether the sender of the message with `header` is authorized for
+    /// `action`.
+    ///
+    /// polkit only asks the user to authenticate if the caller allowed interactive
+    /// authorization on the message, as requests are not handled meanwhile.
+    pub fn check(&self, header: &MessageHeader<'_>, action: Action) -> fdo::Result<()> {
+        let subject = Subject::new_for_message_header(header)
+            .map_err(|err| fdo::Error::Failed(format!("cannot identify caller: {}", err)))?;
+        let interactive = header
+            .primary()
+            .flags()
+            .contains(MessageFlags::AllowInteractiveAuth);
+        let flags = if interactive {
+            CheckAuthorizationFlags::AllowUserInteraction.into()
+        } else {
+            Default::default()
+        };
+        let result = self
+            .proxy
+            .check_authorization(&subject, action.id(), HashMap::new(), flags, "")
+            .map_err(|err| fdo::Error::Failed(format!("cannot check authorization: {}", err)))?;
+        if result.is_authorized {
+            Ok(())
+        } else if result.is_challenge && !interactive {
+            Err(fdo::Error::InteractiveAuthorizationRequired(format!(
+                "{} requires authentication",
+                action.id()
+            )))
+        } else {
+            Err(fdo::Error::AccessDenied(format!(
+                "not authorized for {}",
+                action.id()
+            )))
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::error::CollectorError;
+use crate::proc_page::{PageCategoryStats, PageFlags};
+use crate::source::{PageFrameSource, CHUNK_FRAMES};
+
+pub use meminfo_common::cgroup::CgroupStats;
+
+/// Where the unified (v2) cgroup hierarchy is usually mounted.
+pub const CGROUP2_ROOT: &str = "/sys/fs/cgroup";
+
+/// Maps the inode numbers of all cgroup directories below `root` to their paths.
+///
+/// Cgroups removed during the walk, as happens whenever e.g. a service stops, are
+/// left out.
+pub fn cgroup_paths(root: &Path) -> io::Result<HashMap<u64, String>> {
+    let mut paths = HashMap::new();
+    paths.insert(fs::metadata(root)?.ino(), "/".to_string());
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let visited = entry.and_then(|entry| {
+                if entry.file_type()?.is_dir() {
+                    let path = entry.path();
+                    let relative = path.strip_prefix(root).expect("entry lies below root");
+                    paths.insert(entry.metadata()?.ino(), format!("/{}", relative.display()));
+                    pending.push(path);
+                }
+                Ok(())
+            });
+            match visited {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Breaks down all present frames of `source` by the memory cgroup they are charged to.
+///
+/// Cgroup inodes are resolved to paths below `root`. The result is sorted by the
+/// number of frames, largest first.
+pub fn cgroup_breakdown<S>(source: &S, root: &Path) -> Result<Vec<CgroupStats>, CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let mut by_inode: HashMap<u64, PageCategoryStats> = HashMap::new();
+    let mut flags = vec![0u64; CHUNK_FRAMES];
+    let mut cgroups = vec![0u64; CHUNK_FRAMES];
+    let mut pfn = 0;
+    loop {
+        let flags_read = source
+            .read_flags(pfn, &mut flags)
+            .map_err(CollectorError::io("kpageflags"))?;
+        let cgroups_read = source
+            .read_cgroups(pfn, &mut cgroups)
+            .map_err(CollectorError::io("kpagecgroup"))?;
+        if flags_read != cgroups_read {
+            return Err(CollectorError::TableLengthMismatch {
+                table: "kpagecgroup",
+                entries: pfn + cgroups_read as u64,
+                flags: pfn + flags_read as u64,
+            });
+        }
+        if flags_read == 0 {
+            break;
+        }
+        for (&raw_flags, &inode) in flags[..flags_read].iter().zip(&cgroups[..cgroups_read]) {
+            let flags = PageFlags::from_bits_truncate(raw_flags);
+            if !flags.contains(PageFlags::NOPAGE) {
+                by_inode.entry(inode).or_default().account(flags);
+            }
+        }
+        pfn += flags_read as u64;
+    }
+
+    let paths = cgroup_paths(root).map_err(CollectorError::io("cgroup hierarchy"))?;
+    let mut breakdown: Vec<_> = by_inode
+        .into_iter()
+        .map(|(inode, stats)| CgroupStats {
+            inode,
+            path: paths.get(&inode).cloned().unwrap_or_default(),
+            stats,
+        })
+        .collect();
+    breakdown.sort_by_key(|cgroup| Reverse(cgroup.stats.total));
+    Ok(breakdown)
+}
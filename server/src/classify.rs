@@ -0,0 +1,432 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::error::CollectorError;
+use crate::frame_table::PageFrameTable;
+use crate::proc_page::{
+    Anomaly, CompoundOrderStats, FrameCategory, Invariant, LruList, PageFlags, PageFrameStats,
+};
+use crate::source::{self, PageFrameSource};
+
+/// The maximum number of anomalies that are reported individually per refresh.
+/// All further ones are only counted.
+pub const MAX_REPORTED_ANOMALIES: usize = 4096;
+
+/// The number of frames in the PFN ranges that are classified independently.
+///
+/// Ranges are aligned to their size, so that no compound page or free buddy block,
+/// which are aligned to their own size, spans two ranges.
+pub const RANGE_FRAMES: u64 = 1 << 18;
+
+/// The kind of user a compound page belongs to, as told by its head page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompoundOwner {
+    HugeTlb,
+    Transparent,
+    Slab,
+    Other,
+}
+
+impl CompoundOwner {
+    fn of_head(flags: PageFlags) -> Self {
+        if flags.contains(PageFlags::HUGE) {
+            CompoundOwner::HugeTlb
+        } else if flags.contains(PageFlags::THP) {
+            CompoundOwner::Transparent
+        } else if flags.contains(PageFlags::SLAB) {
+            CompoundOwner::Slab
+        } else {
+            CompoundOwner::Other
+        }
+    }
+}
+
+/// A compound page whose head has been seen, but whose end has not been reached yet.
+#[derive(Debug)]
+struct OpenCompound {
+    head: Anomaly,
+    owner: CompoundOwner,
+    frames: u64,
+}
+
+/// The highest order of free blocks managed by the buddy allocator.
+pub const MAX_BUDDY_ORDER: u32 = 10;
+
+/// A free block whose first frame has been seen, together with the frames following
+/// it that belong to it.
+#[derive(Debug)]
+struct OpenFreeBlock {
+    frames: u64,
+    /// The size of the largest block that may start at the first frame, as blocks
+    /// are aligned to their size.
+    max_frames: u64,
+    /// Whether the frames after the first carry the BUDDY flag as well, `None` while
+    /// only the first frame has been seen.
+    tail_flagged: Option<bool>,
+}
+
+impl OpenFreeBlock {
+    fn new(start_pfn: u64) -> Self {
+        let max_order = start_pfn.trailing_zeros().min(MAX_BUDDY_ORDER);
+        Self {
+            frames: 1,
+            max_frames: 1 << max_order,
+            tail_flagged: None,
+        }
+    }
+
+    /// Adds the next frame to the block if it fits into its alignment and is flagged
+    /// like the block's other frames after the first. Returns whether it was added.
+    fn extend(&mut self, flagged: bool) -> bool {
+        let fits = self.frames < self.max_frames
+            && self
+                .tail_flagged
+                .map_or(true, |tail_flagged| tail_flagged == flagged);
+        if fits {
+            self.frames += 1;
+            self.tail_flagged = Some(flagged);
+        }
+        fits
+    }
+
+    /// Counts the frames seen as blocks of the largest orders they fill, which stay
+    /// aligned as the first frame is aligned to at least `max_frames`.
+    ///
+    /// Unused frames right after a block that belong to no block, e.g. as they are on
+    /// the allocator's per-CPU lists, look just like the block's own frames on older
+    /// kernels and may make it appear larger.
+    fn count(&self, blocks: &mut Vec<u64>) {
+        let mut remaining = self.frames;
+        while remaining > 0 {
+            let order = 63 - remaining.leading_zeros();
+            if blocks.len() <= order as usize {
+                blocks.resize(order as usize + 1, 0);
+            }
+            blocks[order as usize] += 1;
+            remaining -= 1 << order;
+        }
+    }
+}
+
+/// Sorts page frames into the categories of [`PageFrameStats`].
+///
+/// Frames need to be added in ascending PFN order, as compound pages are grouped
+/// by walking from their head through their tail frames.
+#[derive(Debug)]
+pub struct Classifier {
+    stats: PageFrameStats,
+    anomalies: Vec<Anomaly>,
+    page_size: u64,
+    compound: Option<OpenCompound>,
+    free_block: Option<OpenFreeBlock>,
+}
+
+impl Classifier {
+    pub fn new(page_size: u64) -> Self {
+        Self {
+            stats: Default::default(),
+            anomalies: Vec::new(),
+            page_size,
+            compound: None,
+            free_block: None,
+        }
+    }
+
+    /// Accounts a single frame. Returns `false` if no frame exists at `pfn`.
+    pub fn add(&mut self, pfn: u64, raw_flags: u64, reference_count: u64) -> bool {
+        let flags = PageFlags::from_bits_truncate(raw_flags);
+        let anomaly = |invariant| Anomaly {
+            pfn,
+            flags: raw_flags,
+            reference_count,
+            invariant,
+        };
+        if flags.contains(PageFlags::NOPAGE) {
+            self.end_range();
+            return false;
+        }
+        self.track_compound(flags, anomaly(Invariant::TailFollowsHead));
+        self.track_free_block(pfn, flags, reference_count);
+        let Classifier {
+            stats, anomalies, ..
+        } = self;
+
+        if let Some(list) = LruList::of(flags) {
+            stats.lru_stats.total += 1;
+            match list {
+                LruList::Active => {
+                    if flags.contains(PageFlags::UNEVICTABLE) {
+                        report(stats, anomalies, anomaly(Invariant::ActiveIsEvictable));
+                    }
+                    stats.lru_stats.active += 1;
+                }
+                LruList::Unevictable => stats.lru_stats.unevictable += 1,
+                LruList::Inactive => stats.lru_stats.inactive += 1,
+            }
+        }
+        match FrameCategory::of(flags) {
+            FrameCategory::Poisoned => stats.poisoned += 1,
+            FrameCategory::Ksm => {
+                stats.shared += 1;
+                if reference_count < 2 {
+                    report(stats, anomalies, anomaly(Invariant::KsmIsShared));
+                }
+            }
+            FrameCategory::Buddy => stats.buddy += 1,
+            FrameCategory::Slab => stats.slab += 1,
+            FrameCategory::PageTable => stats.pagetable += 1,
+            FrameCategory::Anon => {
+                stats.mmaped_stats.total += 1;
+                stats.mmaped_stats.anon += 1;
+            }
+            FrameCategory::File => {
+                stats.mmaped_stats.total += 1;
+                stats.mmaped_stats.file += 1;
+            }
+            FrameCategory::ZeroPage => stats.zero += 1,
+            FrameCategory::Reserved => stats.reserved += 1,
+            FrameCategory::Free => {
+                stats.free_stats.total += 1;
+                stats.free_stats.noflag += 1;
+            }
+            FrameCategory::PreviouslyUsed => {
+                stats.free_stats.total += 1;
+                stats.free_stats.previously_used += 1;
+                if reference_count != 0 {
+                    report(stats, anomalies, anomaly(Invariant::UnusedIsUnreferenced));
+                }
+            }
+        }
+        if flags.contains(PageFlags::HUGE) {
+            if flags.intersects(PageFlags::COMPOUND_HEAD) {
+                stats.huge_stats.reserved += 1;
+                stats.huge_stats.total += 1;
+            }
+            stats.huge_stats.reserved_fine_granular += 1;
+            stats.huge_stats.total_fine_granular += 1;
+        }
+        if flags.contains(PageFlags::THP) {
+            if flags.intersects(PageFlags::COMPOUND_HEAD) {
+                stats.huge_stats.transparent += 1;
+                stats.huge_stats.total += 1;
+            }
+            stats.huge_stats.transparent_fine_granular += 1;
+            stats.huge_stats.total_fine_granular += 1;
+        }
+        if flags.contains(PageFlags::MLOCKED) {
+            stats.mlocked += 1;
+        }
+        if reference_count > 0 {
+            stats.frames_in_use += 1;
+        }
+        stats.observed_flags |= raw_flags;
+        stats.total_frames += 1;
+
+        true
+    }
+
+    /// Groups compound pages by extending the group of the last head with each
+    /// directly following tail frame.
+    fn track_compound(&mut self, flags: PageFlags, frame: Anomaly) {
+        if flags.contains(PageFlags::COMPOUND_HEAD) {
+            self.close_compound();
+            self.compound = Some(OpenCompound {
+                owner: CompoundOwner::of_head(flags),
+                frames: 1,
+                head: frame,
+            });
+        } else if flags.contains(PageFlags::COMPOUND_TAIL) {
+            match &mut self.compound {
+                Some(compound) if compound.head.pfn + compound.frames == frame.pfn => {
+                    compound.frames += 1
+                }
+                _ => report(&mut self.stats, &mut self.anomalies, frame),
+            }
+        } else {
+            self.close_compound();
+        }
+    }
+
+    /// Accounts the compound page that is currently being walked, if any.
+    fn close_compound(&mut self) {
+        let compound = match self.compound.take() {
+            Some(compound) => compound,
+            None => return,
+        };
+        if !compound.frames.is_power_of_two() {
+            let head = Anomaly {
+                invariant: Invariant::CompoundIsPowerOfTwo,
+                ..compound.head
+            };
+            report(&mut self.stats, &mut self.anomalies, head);
+        }
+        let order = 63 - compound.frames.leading_zeros();
+        let bytes = compound.frames * self.page_size;
+        let compound_stats = &mut self.stats.compound_stats;
+        compound_stats.total += 1;
+        compound_stats.total_frames += compound.frames;
+        let by_order = match compound.owner {
+            CompoundOwner::HugeTlb => &mut compound_stats.huge_tlb,
+            CompoundOwner::Transparent => &mut compound_stats.transparent,
+            CompoundOwner::Slab => &mut compound_stats.slab,
+            CompoundOwner::Other => &mut compound_stats.other,
+        };
+        CompoundOrderStats::account(by_order, order, 1, bytes);
+    }
+
+    /// Infers the extent of free buddy blocks.
+    ///
+    /// The kernel sets PG_buddy only on the first frame of a free block, which older
+    /// kernels report as is. Newer ones report the BUDDY flag on every frame of the
+    /// block. A block thus starts at a frame with the BUDDY flag and extends over the
+    /// following unused frames up to its alignment, as long as they are all flagged
+    /// or all unflagged. Any other flagged frame starts the next block.
+    fn track_free_block(&mut self, pfn: u64, flags: PageFlags, reference_count: u64) {
+        let is_buddy = flags == PageFlags::BUDDY;
+        let is_unused = (flags.is_empty() || is_buddy) && reference_count == 0;
+        if let Some(block) = &mut self.free_block {
+            if is_unused && block.extend(is_buddy) {
+                return;
+            }
+        }
+        self.close_free_block();
+        if flags.contains(PageFlags::BUDDY) {
+            self.free_block = Some(OpenFreeBlock::new(pfn));
+        }
+    }
+
+    /// Accounts the free block that is currently being walked, if any.
+    fn close_free_block(&mut self) {
+        if let Some(block) = self.free_block.take() {
+            block.count(&mut self.stats.buddy_stats.blocks);
+        }
+    }
+
+    /// Finishes the groups of frames that are still open at the end of a PFN range.
+    pub(crate) fn end_range(&mut self) {
+        self.close_compound();
+        self.close_free_block();
+    }
+
+    /// Adds the results of a classifier that saw a disjoint set of frames.
+    pub fn merge(&mut self, mut other: Classifier) {
+        other.end_range();
+        self.stats.merge(&other.stats);
+        let free_slots = MAX_REPORTED_ANOMALIES - self.anomalies.len();
+        self.anomalies
+            .extend(other.anomalies.into_iter().take(free_slots));
+    }
+
+    /// Checks the collected statistics for consistency and returns them together
+    /// with the anomalies found on the way.
+    pub fn finish(mut self) -> Result<(PageFrameStats, Vec<Anomaly>), CollectorError> {
+        self.end_range();
+        let stats = self.stats;
+        let classified = stats.mmaped_stats.total
+            + stats.slab
+            + stats.buddy
+            + stats.zero
+            + stats.poisoned
+            + stats.pagetable
+            + stats.shared
+            + stats.reserved
+            + stats.free_stats.total;
+        if classified != stats.total_frames {
+            return Err(CollectorError::InconsistentStats {
+                classified,
+                total: stats.total_frames,
+            });
+        }
+        Ok((stats, self.anomalies))
+    }
+}
+
+/// Counts an anomaly and keeps it for the report, unless enough have been reported already.
+fn report(stats: &mut PageFrameStats, anomalies: &mut Vec<Anomaly>, anomaly: Anomaly) {
+    stats.anomalies += 1;
+    if anomalies.len() < MAX_REPORTED_ANOMALIES {
+        anomalies.push(anomaly);
+    }
+}
+
+/// Classifies all frames of `source` using `workers` threads.
+///
+/// The PFN space is split into ranges of [`RANGE_FRAMES`] frames that workers pick up
+/// one after another until the end of the source has been reached. With a single
+/// worker, all frames are classified on the calling thread.
+pub fn classify_frames<S>(
+    source: &S,
+    workers: usize,
+) -> Result<(Classifier, PageFrameTable), CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    if workers <= 1 {
+        let (classifier, table, _) = classify_range(source, 0..u64::MAX)?;
+        return Ok((classifier, table));
+    }
+
+    let next_range = AtomicU64::new(0);
+    let ranges = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| -> Result<(), CollectorError> {
+                    loop {
+                        let index = next_range.fetch_add(1, Ordering::Relaxed);
+                        let start = index * RANGE_FRAMES;
+                        let (classifier, table, frames_read) =
+                            classify_range(source, start..start + RANGE_FRAMES)?;
+                        if frames_read > 0 {
+                            ranges.lock().unwrap().push((index, classifier, table));
+                        }
+                        if frames_read < RANGE_FRAMES {
+                            return Ok(());
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().expect("classification worker panicked"))
+    })?;
+
+    let mut ranges = ranges.into_inner().unwrap();
+    ranges.sort_by_key(|(index, _, _)| *index);
+    let mut classifier = Classifier::new(source.page_size());
+    let mut table = PageFrameTable::new();
+    for (_, range_classifier, range_table) in ranges {
+        classifier.merge(range_classifier);
+        table.append(range_table);
+    }
+    Ok((classifier, table))
+}
+
+/// Classifies the frames in `pfns`. Also returns the number of frames read, which is
+/// less than the length of `pfns` if the source ends within the range.
+fn classify_range<S>(
+    source: &S,
+    pfns: Range<u64>,
+) -> Result<(Classifier, PageFrameTable, u64), CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let mut classifier = Classifier::new(source.page_size());
+    let mut table = PageFrameTable::new();
+    let mut frames_read = 0;
+    source::for_each_chunk(source, pfns, |chunk| {
+        let frames = chunk.flags.iter().zip(chunk.counts).zip(chunk.start_pfn..);
+        for ((&flags, &reference_count), pfn) in frames {
+            if classifier.add(pfn, flags, reference_count) {
+                table.push(pfn, flags, reference_count);
+            }
+        }
+        frames_read += chunk.flags.len() as u64;
+        Ok(())
+    })?;
+    classifier.end_range();
+    Ok((classifier, table, frames_read))
+}
@@ -0,0 +1,192 @@
+//! Compares two page frame tables, e.g. of snapshots taken at different times.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::frame_table::PageFrameTable;
+use crate::proc_page::{FrameCategory, LruList, PageFlags, PageFrame};
+
+/// How many frames moved from one state to another. `None` stands for frames that
+/// were not present, or not on any LRU list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Transition<T> {
+    pub from: Option<T>,
+    pub to: Option<T>,
+    pub frames: u64,
+}
+
+/// How many frames gained or lost a flag while staying present.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FlagChange {
+    /// The name of the flag, as in [`PageFlags`].
+    pub flag: String,
+    pub gained: u64,
+    pub lost: u64,
+}
+
+/// A run of consecutive frames that all moved from one category to another.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedRange {
+    pub start_pfn: u64,
+    /// The PFN following the last frame of the range.
+    pub end_pfn: u64,
+    pub from: Option<FrameCategory>,
+    pub to: Option<FrameCategory>,
+}
+
+/// The differences between two page frame tables.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FrameDiff {
+    /// The frames per pair of [`FrameCategory`], including the frames that kept
+    /// their category, ordered by the categories.
+    pub categories: Vec<Transition<FrameCategory>>,
+    /// The frames per pair of [`LruList`], like `categories`.
+    pub lru_lists: Vec<Transition<LruList>>,
+    /// The flags that changed on any frame, ordered by their bit.
+    pub flags: Vec<FlagChange>,
+    /// The ranges of frames whose category changed, in ascending PFN order.
+    pub changed_ranges: Vec<ChangedRange>,
+}
+
+impl FrameDiff {
+    /// The number of frames that moved from `from` to `to`.
+    pub fn category_frames(&self, from: Option<FrameCategory>, to: Option<FrameCategory>) -> u64 {
+        transition_frames(&self.categories, from, to)
+    }
+
+    /// The number of frames that moved from the LRU list `from` to `to`.
+    pub fn lru_frames(&self, from: Option<LruList>, to: Option<LruList>) -> u64 {
+        transition_frames(&self.lru_lists, from, to)
+    }
+
+    /// The transitions between different categories, the largest first.
+    pub fn category_changes(&self) -> Vec<&Transition<FrameCategory>> {
+        changes(&self.categories)
+    }
+
+    /// The transitions between different LRU lists, the largest first.
+    pub fn lru_changes(&self) -> Vec<&Transition<LruList>> {
+        changes(&self.lru_lists)
+    }
+}
+
+fn transition_frames<T: PartialEq>(
+    transitions: &[Transition<T>],
+    from: Option<T>,
+    to: Option<T>,
+) -> u64 {
+    transitions
+        .iter()
+        .find(|transition| transition.from == from && transition.to == to)
+        .map_or(0, |transition| transition.frames)
+}
+
+fn changes<T: PartialEq>(transitions: &[Transition<T>]) -> Vec<&Transition<T>> {
+    let mut changes: Vec<_> = transitions
+        .iter()
+        .filter(|transition| transition.from != transition.to)
+        .collect();
+    changes.sort_by_key(|transition| Reverse(transition.frames));
+    changes
+}
+
+/// Compares the frames of `before` with the frames at the same PFNs in `after`.
+///
+/// Frames present in only one of the tables, e.g. due to memory hotplug, move from
+/// or to `None`. Flags are only compared for frames present in both.
+pub fn diff_frames(before: &PageFrameTable, after: &PageFrameTable) -> FrameDiff {
+    let mut categories = BTreeMap::new();
+    let mut lru_lists = BTreeMap::new();
+    let mut gained = [0u64; 64];
+    let mut lost = [0u64; 64];
+    let mut changed_ranges: Vec<ChangedRange> = Vec::new();
+
+    for (pfn, before, after) in join(before.iter(), after.iter()) {
+        let from = before.map(|frame| FrameCategory::of(frame.flags));
+        let to = after.map(|frame| FrameCategory::of(frame.flags));
+        *categories.entry((from, to)).or_insert(0) += 1;
+        let lru_of = |frame: Option<PageFrame>| frame.and_then(|frame| LruList::of(frame.flags));
+        *lru_lists
+            .entry((lru_of(before), lru_of(after)))
+            .or_insert(0) += 1;
+
+        if let (Some(before), Some(after)) = (before, after) {
+            let changed = before.flags.bits() ^ after.flags.bits();
+            for bit in (0..64).filter(|bit| changed & (1 << bit) != 0) {
+                if after.flags.bits() & (1 << bit) != 0 {
+                    gained[bit] += 1;
+                } else {
+                    lost[bit] += 1;
+                }
+            }
+        }
+
+        if from != to {
+            match changed_ranges.last_mut() {
+                Some(range) if range.end_pfn == pfn && range.from == from && range.to == to => {
+                    range.end_pfn += 1
+                }
+                _ => changed_ranges.push(ChangedRange {
+                    start_pfn: pfn,
+                    end_pfn: pfn + 1,
+                    from,
+                    to,
+                }),
+            }
+        }
+    }
+
+    let flags = (0..64)
+        .filter(|&bit| gained[bit] + lost[bit] > 0)
+        .map(|bit| FlagChange {
+            flag: PageFlags::names(1 << bit).remove(0),
+            gained: gained[bit],
+            lost: lost[bit],
+        })
+        .collect();
+    FrameDiff {
+        categories: transitions(categories),
+        lru_lists: transitions(lru_lists),
+        flags,
+        changed_ranges,
+    }
+}
+
+fn transitions<T>(counts: BTreeMap<(Option<T>, Option<T>), u64>) -> Vec<Transition<T>> {
+    counts
+        .into_iter()
+        .map(|((from, to), frames)| Transition { from, to, frames })
+        .collect()
+}
+
+/// Joins two streams of frames in ascending PFN order on their PFN, yielding each
+/// PFN present in either of them once.
+fn join<A, B>(
+    before: A,
+    after: B,
+) -> impl Iterator<Item = (u64, Option<PageFrame>, Option<PageFrame>)>
+where
+    A: Iterator<Item = (u64, PageFrame)>,
+    B: Iterator<Item = (u64, PageFrame)>,
+{
+    let (mut before, mut after) = (before.peekable(), after.peekable());
+    std::iter::from_fn(move || {
+        let next_before = before.peek().map(|(pfn, _)| *pfn);
+        let next_after = after.peek().map(|(pfn, _)| *pfn);
+        let pfn = match (next_before, next_after) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) => a,
+            (None, Some(b)) => b,
+            (None, None) => return None,
+        };
+        let before = before
+            .next_if(|(next, _)| *next == pfn)
+            .map(|(_, frame)| frame);
+        let after = after
+            .next_if(|(next, _)| *next == pfn)
+            .map(|(_, frame)| frame);
+        Some((pfn, before, after))
+    })
+}
@@ -0,0 +1,106 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use procfs::ProcError;
+use zbus::fdo;
+
+/// Errors that prevent the collector from producing page frame statistics.
+#[derive(Debug)]
+pub enum CollectorError {
+    /// Reading one of the kernel's tables failed.
+    Io {
+        /// What could not be read, e.g. `kpageflags`.
+        table: &'static str,
+        source: io::Error,
+    },
+    /// A page frame table does not describe the same number of frames as kpageflags.
+    TableLengthMismatch {
+        table: &'static str,
+        entries: u64,
+        flags: u64,
+    },
+    /// The classified frames do not add up to the number of present frames.
+    InconsistentStats { classified: u64, total: u64 },
+    /// Inspecting a process failed, e.g. as it does not exist.
+    Process { pid: u32, source: ProcError },
+    /// Probing a file failed.
+    File { path: PathBuf, source: io::Error },
+    /// A query covers more frames than allowed at once.
+    RangeTooLarge { frames: u64, limit: u64 },
+    /// Writing a snapshot failed.
+    Snapshot { source: io::Error },
+}
+
+impl CollectorError {
+    pub(crate) fn io(table: &'static str) -> impl FnOnce(io::Error) -> Self {
+        move |source| CollectorError::Io { table, source }
+    }
+}
+
+impl fmt::Display for CollectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectorError::Io { table, source } => write!(f, "cannot read {}: {}", table, source),
+            CollectorError::TableLengthMismatch {
+                table,
+                entries,
+                flags,
+            } => write!(
+                f,
+                "{} has {} entries, but kpageflags has {}",
+                table, entries, flags
+            ),
+            CollectorError::InconsistentStats { classified, total } => write!(
+                f,
+                "classified {} page frames, but {} are present",
+                classified, total
+            ),
+            CollectorError::Process { pid, source } => {
+                write!(f, "cannot inspect process {}: {}", pid, source)
+            }
+            CollectorError::File { path, source } => {
+                write!(f, "cannot probe {}: {}", path.display(), source)
+            }
+            CollectorError::RangeTooLarge { frames, limit } => write!(
+                f,
+                "cannot query {} page frames at once, the limit is {}",
+                frames, limit
+            ),
+            CollectorError::Snapshot { source } => write!(f, "cannot write snapshot: {}", source),
+        }
+    }
+}
+
+impl Error for CollectorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CollectorError::Io { source, .. } => Some(source),
+            CollectorError::Process { source, .. } => Some(source),
+            CollectorError::File { source, .. } => Some(source),
+            CollectorError::Snapshot { source } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<CollectorError> for fdo::Error {
+    fn from(err: CollectorError) -> Self {
+        match err {
+            CollectorError::Io { .. }
+            | CollectorError::File { .. }
+            | CollectorError::Snapshot { .. } => fdo::Error::IOError(err.to_string()),
+            CollectorError::Process {
+                source: ProcError::NotFound(_),
+                ..
+            } => fdo::Error::InvalidArgs(err.to_string()),
+            CollectorError::Process {
+                source: ProcError::PermissionDenied(_),
+                ..
+            } => fdo::Error::AccessDenied(err.to_string()),
+            CollectorError::RangeTooLarge { .. } => fdo::Error::InvalidArgs(err.to_string()),
+            _ => fdo::Error::Failed(err.to_string()),
+        }
+    }
+}
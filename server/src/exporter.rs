@@ -0,0 +1,331 @@
+//! Serves the page frame statistics as OpenMetrics gauges over HTTP, for being
+//! scraped by e.g. Prometheus.
+//!
+//! Each scrape refreshes the page frames, unless the last refresh happened less than
+//! [`MIN_UPDATE_INTERVAL`] ago, so that several scrapers do not each read all frames.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::error::CollectorError;
+use crate::proc_page::{CompoundOrderStats, PageFrameStats};
+use crate::service::MIN_UPDATE_INTERVAL;
+use crate::source::{PageFrameSource, ProcfsSource};
+use crate::MeminfoCollector;
+
+/// The content type of the OpenMetrics text format.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The path the metrics are served at.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Requests with a longer head are rejected.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Formats `stats` in the OpenMetrics text format.
+///
+/// All page frame counters are reported in the `meminfo_page_frames` family, labeled
+/// with the same categories `meminfo-cli` prints. Multiply by
+/// `meminfo_page_size_bytes` for their size.
+pub fn encode(stats: &PageFrameStats, page_size: u64) -> String {
+    let mut out = String::new();
+    family(
+        &mut out,
+        "meminfo_page_size_bytes",
+        "The size of a page frame in bytes.",
+    );
+    writeln!(out, "meminfo_page_size_bytes {}", page_size).unwrap();
+
+    family(
+        &mut out,
+        "meminfo_page_frames",
+        "Physical page frames by category.",
+    );
+    let compound_frames = |pages: &[CompoundOrderStats]| {
+        pages.iter().map(|order| order.bytes).sum::<u64>() / page_size
+    };
+    let frames = [
+        ("total", "present", stats.total_frames),
+        ("total", "in_use", stats.frames_in_use),
+        ("lru", "total", stats.lru_stats.total),
+        ("lru", "active", stats.lru_stats.active),
+        ("lru", "inactive", stats.lru_stats.inactive),
+        ("lru", "unevictable", stats.lru_stats.unevictable),
+        ("mmap", "total", stats.mmaped_stats.total),
+        ("mmap", "anon", stats.mmaped_stats.anon),
+        ("mmap", "file", stats.mmaped_stats.file),
+        ("free", "total", stats.free_stats.total),
+        ("free", "never_used", stats.free_stats.noflag),
+        ("free", "previously_used", stats.free_stats.previously_used),
+        ("buddy", "free", stats.buddy_stats.free_frames()),
+        ("compound", "total", stats.compound_stats.total_frames),
+        (
+            "compound",
+            "hugetlb",
+            compound_frames(&stats.compound_stats.huge_tlb),
+        ),
+        (
+            "compound",
+            "transparent",
+            compound_frames(&stats.compound_stats.transparent),
+        ),
+        (
+            "compound",
+            "slab",
+            compound_frames(&stats.compound_stats.slab),
+        ),
+        (
+            "compound",
+            "other",
+            compound_frames(&stats.compound_stats.other),
+        ),
+        ("huge", "total", stats.huge_stats.total_fine_granular),
+        ("huge", "hugetlb", stats.huge_stats.reserved_fine_granular),
+        (
+            "huge",
+            "transparent",
+            stats.huge_stats.transparent_fine_granular,
+        ),
+        ("kernel", "slab", stats.slab),
+        ("kernel", "pagetable", stats.pagetable),
+        ("kernel", "reserved", stats.reserved),
+        ("kernel", "zero", stats.zero),
+        ("other", "ksm", stats.shared),
+        ("other", "poisoned", stats.poisoned),
+        ("other", "mlocked", stats.mlocked),
+    ];
+    for (category, subcategory, value) in &frames {
+        writeln!(
+            out,
+            "meminfo_page_frames{{category=\"{}\",subcategory=\"{}\"}} {}",
+            category, subcategory, value
+        )
+        .unwrap();
+    }
+
+    family(
+        &mut out,
+        "meminfo_huge_pages",
+        "HugeTLB and transparent huge pages, each spanning several frames.",
+    );
+    writeln!(
+        out,
+        "meminfo_huge_pages{{kind=\"hugetlb\"}} {}",
+        stats.huge_stats.reserved
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "meminfo_huge_pages{{kind=\"transparent\"}} {}",
+        stats.huge_stats.transparent
+    )
+    .unwrap();
+
+    family(
+        &mut out,
+        "meminfo_buddy_free_blocks",
+        "Free blocks of the buddy allocator by order.",
+    );
+    for (order, count) in stats.buddy_stats.block_counts().iter().enumerate() {
+        writeln!(
+            out,
+            "meminfo_buddy_free_blocks{{order=\"{}\"}} {}",
+            order, count
+        )
+        .unwrap();
+    }
+
+    family(
+        &mut out,
+        "meminfo_page_frame_anomalies",
+        "Page frames that changed inconsistently while being read.",
+    );
+    writeln!(out, "meminfo_page_frame_anomalies {}", stats.anomalies).unwrap();
+
+    out.push_str("# EOF\n");
+    out
+}
+
+/// Writes the metadata of the gauge family `name`.
+fn family(out: &mut String, name: &str, help: &str) {
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+}
+
+/// Where the exporter accepts connections.
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listens on `address`, which is either a path to a Unix socket, starting with
+    /// `/`, or a host and port.
+    ///
+    /// A socket left behind at the path by an earlier run is replaced. The socket is
+    /// accessible to everyone, so restrict access through its directory if needed.
+    pub fn bind(address: &str) -> io::Result<Self> {
+        if !address.starts_with('/') {
+            return Ok(Listener::Tcp(TcpListener::bind(address)?));
+        }
+        let path = Path::new(address);
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", address),
+                ))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+        Ok(Listener::Unix(listener))
+    }
+}
+
+/// Answers scrapes with the statistics of a [`MeminfoCollector`].
+pub struct Exporter<S: PageFrameSource = ProcfsSource> {
+    collector: MeminfoCollector<S>,
+    /// The metrics of the last refresh and when it happened.
+    last: Option<(Instant, String)>,
+}
+
+impl<S: PageFrameSource> Exporter<S> {
+    pub fn new(collector: MeminfoCollector<S>) -> Self {
+        Self {
+            collector,
+            last: None,
+        }
+    }
+
+    /// The current metrics, refreshing the page frames if the last refresh is older
+    /// than [`MIN_UPDATE_INTERVAL`].
+    pub fn scrape(&mut self) -> Result<&str, CollectorError> {
+        let now = Instant::now();
+        let fresh = self
+            .last
+            .as_ref()
+            .is_some_and(|(refreshed, _)| now.duration_since(*refreshed) < MIN_UPDATE_INTERVAL);
+        if !fresh {
+            let (stats, _) = self.collector.refresh()?;
+            let metrics = encode(&stats, self.collector.page_size());
+            self.last = Some((now, metrics));
+        }
+        Ok(&self.last.as_ref().expect("metrics were encoded").1)
+    }
+
+    /// Accepts connections on `listener` and answers a single request on each, until
+    /// accepting fails.
+    ///
+    /// Connections are handled one after another, as scrapes are rare and each
+    /// refresh reads all page frames anyway.
+    pub fn serve(&mut self, listener: &Listener) -> io::Result<()> {
+        loop {
+            let result = match listener {
+                Listener::Tcp(listener) => {
+                    let (stream, _) = listener.accept()?;
+                    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+                    self.handle::<TcpStream>(stream)
+                }
+                Listener::Unix(listener) => {
+                    let (stream, _) = listener.accept()?;
+                    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+                    self.handle::<UnixStream>(stream)
+                }
+            };
+            if let Err(err) = result {
+                eprintln!("cannot answer scrape: {}", err);
+            }
+        }
+    }
+
+    /// Reads a single HTTP request from `stream` and answers it.
+    ///
+    /// Only `GET` requests for [`METRICS_PATH`] are served, the connection is closed
+    /// after each response.
+    pub fn handle<C: Read + Write>(&mut self, mut stream: C) -> io::Result<()> {
+        let request = read_request_line(&mut stream)?;
+        let mut parts = request.as_deref().unwrap_or_default().split(' ');
+        let response = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/") => {
+                // Query parameters, as sent by some scrapers, are ignored.
+                let path = path.split('?').next().unwrap_or_default();
+                if method != "GET" {
+                    response(
+                        "405 Method Not Allowed",
+                        &[("Allow", "GET")],
+                        "only GET is supported\n",
+                    )
+                } else if path != METRICS_PATH {
+                    response("404 Not Found", &[], "not found\n")
+                } else {
+                    match self.scrape() {
+                        Ok(metrics) => {
+                            response("200 OK", &[("Content-Type", CONTENT_TYPE)], metrics)
+                        }
+                        Err(err) => {
+                            response("500 Internal Server Error", &[], &format!("{}\n", err))
+                        }
+                    }
+                }
+            }
+            _ => response("400 Bad Request", &[], "malformed request\n"),
+        };
+        stream.write_all(response.as_bytes())?;
+        stream.flush()
+    }
+}
+
+/// Reads the head of an HTTP request up to the empty line ending it and returns its
+/// first line, or `None` if the head is malformed or too large.
+fn read_request_line<C: Read>(stream: &mut C) -> io::Result<Option<String>> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.ends_with(b"\r\n\r\n") && !head.ends_with(b"\n\n") {
+        if head.len() >= MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        let read = stream.read(&mut buf)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    let head = match String::from_utf8(head) {
+        Ok(head) => head,
+        Err(_) => return Ok(None),
+    };
+    Ok(head.lines().next().map(|line| line.trim_end().to_string()))
+}
+
+/// Formats a complete HTTP response. Bodies are plain text unless `headers` set
+/// another `Content-Type`.
+fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {}\r\n", status);
+    if !headers.iter().any(|(name, _)| *name == "Content-Type") {
+        response.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    }
+    for (name, value) in headers {
+        write!(response, "{}: {}\r\n", name, value).unwrap();
+    }
+    write!(
+        response,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    response
+}
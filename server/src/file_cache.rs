@@ -0,0 +1,252 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use procfs::process::MMapPath;
+
+use crate::error::CollectorError;
+use crate::frame_table::PageFrameTable;
+use crate::privileges::with_capabilities;
+use crate::proc_page::PageFlags;
+use crate::process::{self, Pagemap};
+
+pub use meminfo_common::file_cache::FileCacheStats;
+
+/// The number of the `cachestat` system call, available since Linux 6.5.
+const SYS_CACHESTAT: libc::c_long = 451;
+
+/// `struct cachestat_range` of the `cachestat` system call.
+#[repr(C)]
+struct CachestatRange {
+    off: u64,
+    /// `0` covers everything from `off` to the end of the file.
+    len: u64,
+}
+
+/// `struct cachestat` of the `cachestat` system call.
+#[repr(C)]
+#[derive(Default)]
+struct Cachestat {
+    nr_cache: u64,
+    nr_dirty: u64,
+    nr_writeback: u64,
+    nr_evicted: u64,
+    nr_recently_evicted: u64,
+}
+
+/// A file mapped by a process, identified by its device and inode.
+type FileId = ((i32, i32), u64);
+
+/// Attributes the file-backed pages mapped by any process to their files.
+///
+/// Only pages mapped at the time of the walk are seen, not the unmapped part of a
+/// file's page cache. A frame mapped by several processes is counted once. Flags
+/// are looked up in `page_frames`, ie. as of the last refresh. The result is sorted
+/// by resident bytes, largest first.
+pub fn mapped_file_cache(
+    page_frames: &PageFrameTable,
+    page_size: u64,
+) -> Result<Vec<FileCacheStats>, CollectorError> {
+    let mut files: HashMap<FileId, (PathBuf, HashSet<u64>)> = HashMap::new();
+    process::for_each_process(|process| {
+        let pid = process.pid as u32;
+        let maps = process::maps(process)?;
+        let pagemap = Pagemap::open(pid, page_size).map_err(CollectorError::io("pagemap"))?;
+        for map in maps {
+            let path = match map.pathname {
+                MMapPath::Path(path) if map.inode != 0 => path,
+                _ => continue,
+            };
+            let (_, frames) = files
+                .entry((map.dev, map.inode))
+                .or_insert_with(|| (path, HashSet::new()));
+            let (start, end) = map.address;
+            pagemap
+                .for_each_entry(start..end, |_, entry| {
+                    if let Some(pfn) = entry.pfn() {
+                        frames.insert(pfn);
+                    }
+                })
+                .map_err(CollectorError::io("pagemap"))?;
+        }
+        Ok(())
+    })?;
+
+    let mut cache: Vec<_> = files
+        .into_values()
+        .filter_map(|(path, frames)| {
+            let mut stats = FileCacheStats {
+                path: path.display().to_string(),
+                ..Default::default()
+            };
+            for pfn in frames {
+                let flags = match page_frames.get(pfn) {
+                    Some(frame) => frame.flags,
+                    None => continue,
+                };
+                // Private mappings of a file turn into anonymous pages once written to.
+                if flags.contains(PageFlags::ANON) {
+                    continue;
+                }
+                stats.account(flags, page_size);
+            }
+            Some(stats).filter(|stats| stats.resident > 0)
+        })
+        .collect();
+    cache.sort_by_key(|stats| Reverse(stats.resident));
+    Ok(cache)
+}
+
+/// Probes how much of the given files is held in the page cache. Directories are
+/// probed recursively, skipping files and directories that vanish or cannot be
+/// opened, as well as anything but regular files. Other paths must be regular files.
+///
+/// Uses the `cachestat` system call if the kernel supports it. Otherwise falls back
+/// to `mincore`, which does not tell dirty or writeback pages. Files without resident
+/// pages are left out. The result is sorted by resident bytes, largest first.
+pub fn probe_file_cache<P: AsRef<Path>>(
+    paths: &[P],
+    page_size: u64,
+) -> Result<Vec<FileCacheStats>, CollectorError> {
+    let mut cache = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let file_error = |source| CollectorError::File {
+            path: path.to_path_buf(),
+            source,
+        };
+        let metadata = with_capabilities(|| fs::metadata(path)).map_err(file_error)?;
+        if metadata.is_dir() {
+            probe_directory(path, page_size, &mut cache).map_err(file_error)?;
+        } else if !metadata.is_file() {
+            return Err(file_error(not_a_regular_file()));
+        } else {
+            cache.push(probe_file(path, page_size).map_err(file_error)?);
+        }
+    }
+    cache.retain(|stats| stats.resident > 0);
+    cache.sort_by_key(|stats| Reverse(stats.resident));
+    Ok(cache)
+}
+
+fn probe_directory(dir: &Path, page_size: u64, cache: &mut Vec<FileCacheStats>) -> io::Result<()> {
+    let mut entries = vec![with_capabilities(|| fs::read_dir(dir))?];
+    while let Some(dir) = entries.pop() {
+        for entry in dir {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                match with_capabilities(|| fs::read_dir(entry.path())) {
+                    Ok(dir) => entries.push(dir),
+                    Err(err) if is_skipped(&err) => {}
+                    Err(err) => return Err(err),
+                }
+            } else if file_type.is_file() {
+                match probe_file(&entry.path(), page_size) {
+                    Ok(stats) => cache.push(stats),
+                    Err(err) if is_skipped(&err) => {}
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the walk of a directory skips a file or directory failing with `err`, as
+/// it vanished, may not be opened or was replaced by another kind of file.
+fn is_skipped(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied | io::ErrorKind::InvalidInput
+    ) || err.raw_os_error() == Some(libc::ENOTDIR)
+}
+
+fn not_a_regular_file() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "not a regular file")
+}
+
+/// Opens the file without blocking, so that a FIFO or device swapped in for a
+/// regular file cannot hang the probe, and rejects it if it is no regular file.
+fn probe_file(path: &Path, page_size: u64) -> io::Result<FileCacheStats> {
+    let file = with_capabilities(|| {
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+    })?;
+    if !file.metadata()?.is_file() {
+        return Err(not_a_regular_file());
+    }
+    let mut stats = FileCacheStats {
+        path: path.display().to_string(),
+        ..Default::default()
+    };
+    match cachestat(&file) {
+        Ok(cachestat) => {
+            stats.resident = cachestat.nr_cache * page_size;
+            stats.dirty = cachestat.nr_dirty;
+            stats.writeback = cachestat.nr_writeback;
+        }
+        Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => {
+            stats.resident = mincore(&file, page_size)? * page_size;
+        }
+        Err(err) => return Err(err),
+    }
+    Ok(stats)
+}
+
+fn cachestat(file: &File) -> io::Result<Cachestat> {
+    let range = CachestatRange { off: 0, len: 0 };
+    let mut cachestat = Cachestat::default();
+    // SAFETY: Both structs outlive the call and match the kernel's layout.
+    let ret = unsafe {
+        libc::syscall(
+            SYS_CACHESTAT,
+            file.as_raw_fd(),
+            &range as *const CachestatRange,
+            &mut cachestat as *mut Cachestat,
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cachestat)
+}
+
+/// Counts the resident pages of `file` by mapping it and asking `mincore`.
+fn mincore(file: &File, page_size: u64) -> io::Result<u64> {
+    let len = file.metadata()?.len() as usize;
+    if len == 0 {
+        return Ok(0);
+    }
+    // SAFETY: The mapping is only passed to mincore and unmapped before returning,
+    // its contents are never accessed.
+    unsafe {
+        let addr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_NONE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        );
+        if addr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mut residency = vec![0u8; len.div_ceil(page_size as usize)];
+        let ret = libc::mincore(addr, len, residency.as_mut_ptr());
+        let err = io::Error::last_os_error();
+        libc::munmap(addr, len);
+        if ret != 0 {
+            return Err(err);
+        }
+        Ok(residency.iter().filter(|&&page| page & 1 != 0).count() as u64)
+    }
+}
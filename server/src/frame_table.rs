@@ -0,0 +1,183 @@
+use std::ops::Range;
+
+use crate::proc_page::{PageFlags, PageFrame};
+
+/// Reference counts at or above this value are stored as this value.
+pub const SATURATED_REFERENCE_COUNT: u64 = u8::MAX as u64;
+
+/// The flags documented as stable, `LOCKED` up to `PAGETABLE`, which are stored in
+/// the low bits as they are.
+const STABLE_FLAGS: u64 = (PageFlags::PAGETABLE.bits() << 1) - 1;
+
+/// The kernel hacking flags stored above the stable ones, `RESERVED` up to
+/// `PRIVATE_2`, shifted down by this.
+const HACKING_FLAGS_SHIFT: u32 =
+    PageFlags::RESERVED.bits().trailing_zeros() - STABLE_FLAGS.count_ones();
+
+/// Packs the flags of a frame into the 32 bits kept per frame, dropping the flags
+/// none of the users of the table look at.
+fn pack_flags(flags: u64) -> u32 {
+    let hacking = (flags >> HACKING_FLAGS_SHIFT) & !STABLE_FLAGS;
+    ((flags & STABLE_FLAGS) | hacking) as u32
+}
+
+fn unpack_flags(packed: u32) -> PageFlags {
+    let packed = u64::from(packed);
+    let hacking = (packed & !STABLE_FLAGS) << HACKING_FLAGS_SHIFT;
+    PageFlags::from_bits_truncate((packed & STABLE_FLAGS) | hacking)
+}
+
+/// A run of consecutive present frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    start_pfn: u64,
+    /// The index of the segment's first frame in the columns.
+    offset: usize,
+    len: usize,
+}
+
+impl Segment {
+    fn end_pfn(&self) -> u64 {
+        self.start_pfn + self.len as u64
+    }
+}
+
+/// A compact table of the page frames present in the system.
+///
+/// Frames are stored column-wise in five bytes: the flags packed into 32 bits and the
+/// reference count saturated to a single byte. Holes in the physical address space
+/// are not stored per frame but as gaps between runs of present frames.
+///
+/// The packed flags keep all flags documented as stable and the kernel hacking flags
+/// from `RESERVED` up to `PRIVATE_2`, which covers the flags the frame categories,
+/// the per-process views and the diff are based on. The architecture specific
+/// flags, `OWNER_PRIVATE`, `UNCACHED`, `SOFTDIRTY` and bits unknown to [`PageFlags`]
+/// are dropped, so a frame carrying only those appears without any flags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageFrameTable {
+    segments: Vec<Segment>,
+    flags: Vec<u32>,
+    reference_counts: Vec<u8>,
+}
+
+impl PageFrameTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Appends a present frame. Frames need to be pushed in ascending PFN order.
+    pub fn push(&mut self, pfn: u64, flags: u64, reference_count: u64) {
+        let offset = self.flags.len();
+        match self.segments.last_mut() {
+            Some(segment) if segment.end_pfn() == pfn => segment.len += 1,
+            last => {
+                debug_assert!(last.map_or(true, |segment| segment.end_pfn() < pfn));
+                self.segments.push(Segment {
+                    start_pfn: pfn,
+                    offset,
+                    len: 1,
+                });
+            }
+        }
+        self.flags.push(pack_flags(flags));
+        self.reference_counts
+            .push(reference_count.min(SATURATED_REFERENCE_COUNT) as u8);
+    }
+
+    /// Appends the frames of a table whose frames all follow the frames of this table.
+    pub fn append(&mut self, other: PageFrameTable) {
+        let offset = self.flags.len();
+        let mut segments = other.segments.into_iter();
+        if let (Some(last), Some(first)) = (self.segments.last_mut(), segments.as_slice().first()) {
+            debug_assert!(last.end_pfn() <= first.start_pfn);
+            if last.end_pfn() == first.start_pfn {
+                last.len += first.len;
+                segments.next();
+            }
+        }
+        self.segments.extend(segments.map(|segment| Segment {
+            offset: segment.offset + offset,
+            ..segment
+        }));
+        self.flags.extend(other.flags);
+        self.reference_counts.extend(other.reference_counts);
+    }
+
+    /// Releases memory reserved while the table was built.
+    pub fn shrink_to_fit(&mut self) {
+        self.segments.shrink_to_fit();
+        self.flags.shrink_to_fit();
+        self.reference_counts.shrink_to_fit();
+    }
+
+    /// The number of present frames.
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    /// The PFN following the last present frame.
+    pub fn end_pfn(&self) -> u64 {
+        self.segments.last().map_or(0, Segment::end_pfn)
+    }
+
+    /// Returns the frame at `pfn`, or `None` if no frame is present there.
+    ///
+    /// Reference counts are saturated at [`SATURATED_REFERENCE_COUNT`], flags are
+    /// limited to the ones kept by the table.
+    pub fn get(&self, pfn: u64) -> Option<PageFrame> {
+        self.index_of(pfn).map(|index| self.frame_at(index))
+    }
+
+    /// Iterates over all present frames in ascending PFN order.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, PageFrame)> + '_ {
+        self.segments.iter().flat_map(move |segment| {
+            (0..segment.len).map(move |i| {
+                (
+                    segment.start_pfn + i as u64,
+                    self.frame_at(segment.offset + i),
+                )
+            })
+        })
+    }
+
+    /// Iterates over the ranges of consecutive present frames.
+    pub fn present_ranges(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        self.segments
+            .iter()
+            .map(|segment| segment.start_pfn..segment.end_pfn())
+    }
+
+    /// The number of bytes used by the table's contents.
+    pub fn memory_usage(&self) -> usize {
+        self.segments.capacity() * std::mem::size_of::<Segment>()
+            + self.flags.capacity() * std::mem::size_of::<u32>()
+            + self.reference_counts.capacity() * std::mem::size_of::<u8>()
+    }
+
+    fn index_of(&self, pfn: u64) -> Option<usize> {
+        let segment = match self
+            .segments
+            .binary_search_by(|segment| segment.start_pfn.cmp(&pfn))
+        {
+            Ok(index) => &self.segments[index],
+            Err(0) => return None,
+            Err(index) => &self.segments[index - 1],
+        };
+        if pfn < segment.end_pfn() {
+            Some(segment.offset + (pfn - segment.start_pfn) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn frame_at(&self, index: usize) -> PageFrame {
+        PageFrame {
+            reference_count: self.reference_counts[index].into(),
+            flags: unpack_flags(self.flags[index]),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Keeps the statistics of past refreshes in rings of fixed size, recent ones at a
+//! fine resolution and older ones at a coarser one.
+//!
+//! A history is saved in a format like the one of [snapshots](crate::snapshot): the
+//! magic bytes `MEMHIST\0` and the format version as a little-endian `u32`, followed
+//! by a zlib stream of the number of samples as a little-endian `u32` and each
+//! [`HistorySample`] as a little-endian `u32` length followed by its D-Bus encoding in
+//! little-endian byte order.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::snapshot::{invalid_data, read_section, read_u32, write_section};
+
+pub use meminfo_common::history::HistorySample;
+
+/// The bytes every saved history starts with.
+pub const MAGIC: &[u8; 8] = b"MEMHIST\0";
+
+/// The version of the format written by [`History::write`]. Readers reject other
+/// versions.
+pub const VERSION: u32 = 1;
+
+/// How finely and how far back a tier of a [`History`] keeps samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tier {
+    /// The tier keeps the latest sample of each interval of this length.
+    pub resolution: Duration,
+    /// The tier holds as many samples as intervals fit into this.
+    pub span: Duration,
+}
+
+/// A sample per second for the last ten minutes and a sample per minute for the last
+/// day.
+pub const DEFAULT_TIERS: [Tier; 2] = [
+    Tier {
+        resolution: Duration::from_secs(1),
+        span: Duration::from_secs(10 * 60),
+    },
+    Tier {
+        resolution: Duration::from_secs(60),
+        span: Duration::from_secs(24 * 60 * 60),
+    },
+];
+
+/// The samples of a tier, in ascending order.
+#[derive(Debug)]
+struct Ring {
+    resolution_ms: u64,
+    capacity: usize,
+    samples: VecDeque<HistorySample>,
+}
+
+impl Ring {
+    fn new(tier: &Tier) -> Self {
+        let resolution_ms = (tier.resolution.as_millis() as u64).max(1);
+        let capacity = ((tier.span.as_millis() as u64 / resolution_ms) as usize).max(1);
+        Self {
+            resolution_ms,
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn record(&mut self, sample: &HistorySample) {
+        let interval = sample.timestamp_ms / self.resolution_ms;
+        // Replaces the sample of the same interval. After the clock was set back, the
+        // samples from what is now the future are dropped as well.
+        while self
+            .samples
+            .back()
+            .is_some_and(|last| last.timestamp_ms / self.resolution_ms >= interval)
+        {
+            self.samples.pop_back();
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample.clone());
+    }
+
+    /// The samples from `start_ms` up to, but excluding, `end_ms`.
+    fn range(&self, start_ms: u64, end_ms: u64) -> impl Iterator<Item = &HistorySample> {
+        let start = self
+            .samples
+            .partition_point(|sample| sample.timestamp_ms < start_ms);
+        let end = self
+            .samples
+            .partition_point(|sample| sample.timestamp_ms < end_ms)
+            .max(start);
+        self.samples.range(start..end)
+    }
+}
+
+/// Bounded rings of samples at different resolutions.
+#[derive(Debug)]
+pub struct History {
+    /// From the finest resolution to the coarsest.
+    rings: Vec<Ring>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(&DEFAULT_TIERS)
+    }
+}
+
+impl History {
+    /// Creates an empty history with `tiers`, ordered from the finest resolution to
+    /// the coarsest.
+    pub fn new(tiers: &[Tier]) -> Self {
+        Self {
+            rings: tiers.iter().map(Ring::new).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rings.iter().all(|ring| ring.samples.is_empty())
+    }
+
+    /// Adds `sample` to each tier.
+    pub fn record(&mut self, sample: HistorySample) {
+        for ring in &mut self.rings {
+            ring.record(&sample);
+        }
+    }
+
+    /// The samples from `start_ms` up to, but excluding, `end_ms`, in ascending
+    /// order.
+    ///
+    /// Each part of the range is covered by the finest tier reaching back that far,
+    /// so the samples get sparser towards the past.
+    pub fn query(&self, start_ms: u64, end_ms: u64) -> Vec<HistorySample> {
+        let mut parts = Vec::new();
+        let mut end_ms = end_ms;
+        for ring in &self.rings {
+            parts.push(ring.range(start_ms, end_ms));
+            if let Some(oldest) = ring.samples.front() {
+                end_ms = end_ms.min(oldest.timestamp_ms);
+            }
+        }
+        parts.into_iter().rev().flatten().cloned().collect()
+    }
+
+    /// All samples of any tier, in ascending order.
+    fn samples(&self) -> Vec<&HistorySample> {
+        let mut samples: Vec<_> = self
+            .rings
+            .iter()
+            .flat_map(|ring| ring.samples.iter())
+            .collect();
+        samples.sort_by_key(|sample| sample.timestamp_ms);
+        samples.dedup_by_key(|sample| sample.timestamp_ms);
+        samples
+    }
+
+    /// Writes the samples of all tiers to `writer`.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        let mut encoder = ZlibEncoder::new(writer, Compression::default());
+        let samples = self.samples();
+        encoder.write_all(&(samples.len() as u32).to_le_bytes())?;
+        for sample in samples {
+            write_section(&mut encoder, sample)?;
+        }
+        encoder.finish()?.flush()
+    }
+
+    /// Reads a history written by [`History::write`] and records its samples, which
+    /// thus fill the tiers of this history even if they differ from the written ones.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if `reader` does not contain a
+    /// history of a supported version, recording nothing.
+    pub fn read_from<R: Read>(&mut self, mut reader: R) -> io::Result<()> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a meminfo history".to_string()));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported history version {}, expected {}",
+                version, VERSION
+            )));
+        }
+
+        let mut decoder = ZlibDecoder::new(reader);
+        let count = read_u32(&mut decoder)?;
+        let samples = (0..count)
+            .map(|_| read_section(&mut decoder))
+            .collect::<io::Result<Vec<HistorySample>>>()?;
+        for sample in samples {
+            self.record(sample);
+        }
+        Ok(())
+    }
+}
@@ -1,203 +1,182 @@
-mod proc_page;
+pub mod authority;
+pub mod cgroup;
+pub mod classify;
+pub mod diff;
+pub mod error;
+pub mod exporter;
+pub mod file_cache;
+pub mod frame_table;
+pub mod history;
+pub mod privileges;
+pub mod process;
+pub mod reverse_map;
+pub mod service;
+pub mod set_size;
+pub mod snapshot;
+pub mod source;
+pub mod working_set;
 
-use std::error::Error;
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::iter::Iterator;
+pub use meminfo_common::proc_page;
 
-use zbus::dbus_interface;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-use proc_page::{PageFlags, PageFrame, PageFrameStats};
+use cgroup::CgroupStats;
+use error::CollectorError;
+use file_cache::FileCacheStats;
+use frame_table::PageFrameTable;
+use proc_page::{Anomaly, PageFlags, PageFrameStats};
+use process::ProcessStats;
+use reverse_map::{FrameOwners, ReverseMap};
+use set_size::{CgroupSetSizes, ProcessSetSizes};
+use snapshot::HostMetadata;
+use source::{PageFrameSource, ProcfsSource};
+use working_set::WorkingSetStats;
 
-pub struct MeminfoCollector {
-    page_count_fd: File,
-    page_flags_fd: File,
-    page_frames: Vec<Option<PageFrame>>,
+pub struct MeminfoCollector<S: PageFrameSource = ProcfsSource> {
+    source: S,
+    page_frames: PageFrameTable,
+    /// The flag bits set on any frame during the last refresh.
+    observed_flags: u64,
+    /// The number of threads classifying page frames.
+    workers: usize,
+    /// Built on demand and dropped on refresh.
+    reverse_map: Option<ReverseMap>,
 }
 
-impl MeminfoCollector {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        let page_count_fd = File::open("/proc/kpagecount")?;
-        let page_flags_fd = File::open("/proc/kpageflags")?;
-
-        Ok(Self {
-            page_count_fd,
-            page_flags_fd,
-            page_frames: Vec::new(),
-        })
-    }
-
-    fn read_page_use_counts(&mut self) -> Vec<u64> {
-        let u64_size = std::mem::size_of::<u64>();
-        self.page_count_fd.seek(SeekFrom::Start(0)).unwrap();
-        let mut page_count_content = Vec::new();
-        let bytes_read = self
-            .page_count_fd
-            .read_to_end(&mut page_count_content)
-            .expect("read /proc/kpagecount");
-        assert_eq!(bytes_read % u64_size, 0);
-
-        let mut counts = Vec::with_capacity(bytes_read / u64_size);
-        counts.extend_from_slice(
-            safe_transmute::transmute_many_pedantic::<u64>(&page_count_content)
-                .expect("transmute u64"),
-        );
-        counts
-    }
-
-    fn read_page_flags(&mut self) -> Vec<PageFlags> {
-        let u64_size = std::mem::size_of::<u64>();
-
-        self.page_flags_fd.seek(SeekFrom::Start(0)).unwrap();
-        let mut page_flags_content = Vec::new();
-        let bytes_read = self
-            .page_flags_fd
-            .read_to_end(&mut page_flags_content)
-            .expect("read /proc/kpageflags");
-        assert_eq!(bytes_read % u64_size, 0);
-
-        let mut flags = Vec::with_capacity(bytes_read / u64_size);
-        flags.extend(
-            safe_transmute::transmute_many_pedantic::<u64>(&page_flags_content)
-                .expect("transmute u64")
-                .iter()
-                .map(|flag_bits| PageFlags::from_bits_truncate(*flag_bits)),
-        );
-        flags
+impl MeminfoCollector<ProcfsSource> {
+    /// Creates a collector for the running kernel.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self::with_source(ProcfsSource::new()?))
     }
 }
 
-#[dbus_interface(name = "de.hpi.felixgohla.meminfo.meminfo_collector")]
-impl MeminfoCollector {
-    fn refresh_physical(&mut self) -> String {
-        let counts = self.read_page_use_counts();
-        let flags = self.read_page_flags().into_iter();
-        let mut page_frames = Vec::with_capacity(counts.len());
-        let mut stats = PageFrameStats::default();
-        page_frames.extend(
-            flags
-                .zip(counts.into_iter())
-                .map(|(flags, reference_count)| {
-                    if !flags.contains(PageFlags::NOPAGE) {
-                        // Stats collection.
-                        if flags.contains(PageFlags::LRU) {
-                            stats.lru_stats.total += 1;
-                            if flags.contains(PageFlags::ACTIVE) {
-                                assert_eq!(flags.contains(PageFlags::UNEVICTABLE), false, "Active pages should not be unevictable.");
-                                stats.lru_stats.active += 1;
-                            } else if flags.contains(PageFlags::UNEVICTABLE) {
-                                stats.lru_stats.unevictable += 1;
-                            } else {
-                                stats.lru_stats.inactive += 1;
-                            }
-                        }
-                        if flags.contains(PageFlags::HWPOISON) {
-                            stats.poisoned += 1;
-                        } else if flags.contains(PageFlags::KSM) {
-                            if reference_count < 2 {
-                                unreachable!();
-                            }
-                            stats.shared += 1;
-                        } else if flags.contains(PageFlags::BUDDY) {
-                            stats.buddy+= 1;
-                        } else if flags.contains(PageFlags::SLAB) {
-                            stats.slab+= 1;
-                        } else if flags.contains(PageFlags::PAGETABLE) {
-                            stats.pagetable += 1;
-                        } else if flags.contains(PageFlags::MMAP) {
-                            stats.mmaped_stats.total += 1;
-                            if flags.contains(PageFlags::ANON) {
-                                stats.mmaped_stats.anon += 1;
-                            } else {
-                                stats.mmaped_stats.file += 1;
-                            }
-                        } else if flags.contains(PageFlags::ZERO_PAGE) {
-                            assert_eq!(flags & PageFlags::all(), flags);
-                            stats.zero += 1;
-                        } else if flags.is_empty() {
-                            stats.free_stats.total += 1;
-                            stats.free_stats.noflag += 1;
-                        } else {
-                            assert_eq!(
-                                reference_count, 0,
-                                "expected unused page (rc=0), but got {} with flags {:?}",
-                                reference_count, flags
-                            );
-                            stats.free_stats.total += 1;
-                            stats.free_stats.previously_used += 1;
-                        }
-                        if flags.intersects(PageFlags::COMPOUND_HEAD | PageFlags::COMPOUND_TAIL) {
-                            stats.compound += 1; // This is wrong.
-                        }
-                        if flags.contains(PageFlags::HUGE) {
-                            if flags.intersects(PageFlags::COMPOUND_HEAD) {
-                                stats.huge_stats.reserved += 1;
-                                stats.huge_stats.total += 1;
-                            }
-                            stats.huge_stats.reserved_fine_granular += 1;
-                            stats.huge_stats.total_fine_granular += 1;
-                        }
-                        if flags.contains(PageFlags::THP) {
-                            if flags.intersects(PageFlags::COMPOUND_HEAD) {
-                                stats.huge_stats.transparent += 1;
-                                stats.huge_stats.total += 1;
-                            }
-                            stats.huge_stats.transparent_fine_granular += 1;
-                            stats.huge_stats.total_fine_granular += 1;
-                        }
-                        if reference_count > 0 {
-                            stats.frames_in_use += 1;
-                        }
-                        stats.total_frames += 1;
-                        Some(PageFrame {
-                            reference_count,
-                            flags,
-                        })
-                    } else {
-                        None
-                    }
-                }),
-        );
-        assert_eq!(
-            stats.mmaped_stats.total
-                + stats.slab
-                + stats.buddy
-                + stats.zero
-                + stats.poisoned
-                + stats.pagetable
-                + stats.shared
-                + stats.free_stats.total,
-            stats.total_frames
-        );
-        self.page_frames = page_frames;
-        let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
-            .expect("get page size sysconf")
-            .unwrap() as u64;
-        println!("stats: {:?}", stats);
-        for (pfn, pf) in self.page_frames.iter().enumerate().filter(|(_, pf)| {
-            pf.is_some()
-                && pf.as_ref().unwrap().flags.intersects(PageFlags::HUGE | PageFlags::THP)
-                /*&& !pf
-                    .as_ref()
-                    .unwrap()
-                    .flags
-                    .intersects(PageFlags::MMAP | PageFlags:: BUDDY | PageFlags::KSM | PageFlags::SLAB | PageFlags::HWPOISON | PageFlags::PAGETABLE)*/
-                    && !pf.as_ref().unwrap().flags.is_empty()
-        }) {
-            println!("0x{:X}: {:?}", pfn as u64 * page_size, pf);
+impl<S: PageFrameSource> MeminfoCollector<S> {
+    /// Creates a collector that reads page frames from an arbitrary source.
+    pub fn with_source(source: S) -> Self {
+        Self {
+            source,
+            page_frames: PageFrameTable::new(),
+            observed_flags: 0,
+            workers: thread::available_parallelism().map_or(1, usize::from),
+            reverse_map: None,
+        }
+    }
+
+    /// Sets the number of threads classifying page frames. Defaults to the number of CPUs.
+    pub fn set_workers(&mut self, workers: usize) {
+        self.workers = workers.max(1);
+    }
+
+    pub fn page_size(&self) -> u64 {
+        self.source.page_size()
+    }
+
+    /// The page frames seen by the last refresh.
+    pub fn page_frames(&self) -> &PageFrameTable {
+        &self.page_frames
+    }
+
+    /// Joins the pages mapped by the process `pid` with the page frames seen by the
+    /// last refresh.
+    pub fn process_stats(&self, pid: u32) -> Result<ProcessStats, CollectorError> {
+        process::process_stats(pid, &self.page_frames, self.source.page_size())
+    }
+
+    /// Re-reads all physical page frames.
+    ///
+    /// Returns statistics about them together with the frames whose state was
+    /// inconsistent while being read.
+    pub fn refresh(&mut self) -> Result<(PageFrameStats, Vec<Anomaly>), CollectorError> {
+        let (classifier, mut page_frames) = classify::classify_frames(&self.source, self.workers)?;
+        let (mut stats, anomalies) = classifier.finish()?;
+        if let Some(buddyinfo) = self
+            .source
+            .free_blocks()
+            .map_err(CollectorError::io("buddyinfo"))?
+        {
+            stats.buddy_stats.buddyinfo = buddyinfo;
         }
-        format!(
-            "PFs: {} {}",
-            self.page_frames.len(),
-            self.page_frames
-                .iter()
-                .filter(|pf| pf.is_some()
-                    && pf
-                        .as_ref()
-                        .unwrap()
-                        .flags
-                        .contains(PageFlags::DIRTY | PageFlags::ANON))
-                .count()
-        )
+        stats.buddy_stats.update_unusable_index();
+        page_frames.shrink_to_fit();
+        self.page_frames = page_frames;
+        self.observed_flags = stats.observed_flags;
+        self.reverse_map = None;
+        Ok((stats, anomalies))
+    }
+
+    /// Refreshes the page frames and writes a snapshot of them to `writer`, see
+    /// [`snapshot`].
+    pub fn write_snapshot<W: Write>(
+        &mut self,
+        writer: W,
+        metadata: &HostMetadata,
+    ) -> Result<(), CollectorError> {
+        let (stats, anomalies) = self.refresh()?;
+        snapshot::write_snapshot(writer, metadata, &stats, &anomalies, &self.source)
+    }
+
+    /// Looks up which processes map the present frames in `pfns`.
+    ///
+    /// The mappings of all processes are indexed on the first lookup after a refresh,
+    /// so owners may be outdated until the next refresh. Flags and reference counts
+    /// are read anew.
+    pub fn lookup_owners(&mut self, pfns: Range<u64>) -> Result<Vec<FrameOwners>, CollectorError> {
+        let reverse_map = match self.reverse_map.take() {
+            Some(reverse_map) => reverse_map,
+            None => ReverseMap::build(self.source.page_size())?,
+        };
+        let owners = reverse_map::frame_owners(&self.source, &reverse_map, pfns);
+        self.reverse_map = Some(reverse_map);
+        owners
+    }
+
+    /// Names the page flags the kernel set on any frame during the last refresh.
+    pub fn emitted_flags(&self) -> Vec<String> {
+        PageFlags::names(self.observed_flags)
+    }
+
+    /// Breaks down all physical page frames by the memory cgroup they are charged to.
+    pub fn cgroup_breakdown(&self) -> Result<Vec<CgroupStats>, CollectorError> {
+        cgroup::cgroup_breakdown(&self.source, Path::new(cgroup::CGROUP2_ROOT))
+    }
+
+    /// Joins the pages mapped by each process with the page frames seen by the last
+    /// refresh, leaving out the VMAs.
+    pub fn process_totals(&self) -> Result<Vec<ProcessStats>, CollectorError> {
+        process::all_process_stats(&self.page_frames, self.source.page_size())
+    }
+
+    /// Computes the set sizes of all processes and memory cgroups, see
+    /// [`set_size::set_sizes`].
+    pub fn set_sizes(&self) -> Result<(Vec<ProcessSetSizes>, Vec<CgroupSetSizes>), CollectorError> {
+        set_size::set_sizes(&self.source, &self.page_frames)
+    }
+
+    /// Attributes the file-backed pages mapped by any process to their files, see
+    /// [`file_cache::mapped_file_cache`].
+    pub fn mapped_file_cache(&self) -> Result<Vec<FileCacheStats>, CollectorError> {
+        file_cache::mapped_file_cache(&self.page_frames, self.source.page_size())
+    }
+
+    /// Probes how much of the given files is held in the page cache, see
+    /// [`file_cache::probe_file_cache`].
+    pub fn probe_file_cache<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+    ) -> Result<Vec<FileCacheStats>, CollectorError> {
+        file_cache::probe_file_cache(paths, self.source.page_size())
+    }
+
+    /// Estimates the working set over `interval` for the frames seen by the last
+    /// refresh, see [`working_set::estimate_working_set`].
+    pub fn estimate_working_set(
+        &self,
+        interval: Duration,
+    ) -> Result<WorkingSetStats, CollectorError> {
+        working_set::estimate_working_set(&self.source, self.page_frames.end_pfn(), interval)
     }
 }
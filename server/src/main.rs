@@ -1,99 +1,346 @@
+use std::cell::RefCell;
 use std::convert::TryInto;
+use std::env;
 use std::error::Error;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
 
+use meminfo_common::{OBJECT_PATH, SERVICE_NAME};
+use meminfo_server::authority::Authority;
+use meminfo_server::exporter::{Exporter, Listener};
+use meminfo_server::privileges;
+use meminfo_server::service::{MeminfoService, MAX_UPDATE_INTERVAL};
 use meminfo_server::MeminfoCollector;
 
-use caps::CapSet;
-use nix::unistd::{Group, User};
-use zbus::{dbus_interface, dbus_proxy, fdo, Connection, ObjectServer};
-use zbus_polkit::policykit1::*;
+use nix::poll::{PollFd, PollFlags};
+use zbus::{fdo, Connection, Message, MessageType, ObjectServer};
+use zvariant::ObjectPath;
+
+const USAGE: &str = "Usage: meminfo-server [--idle-timeout SECONDS] [--export ADDRESS]
+                      [--record SECONDS] [--history-file PATH]
+                      [--user USER] [--group GROUP] [--workers N]
+
+Options:
+    --idle-timeout SECONDS  Exit after SECONDS without requests, for being started
+                            on demand by D-Bus activation. Runs forever if 0 or
+                            not given.
+    --export ADDRESS        Serve OpenMetrics over HTTP on ADDRESS instead of
+                            D-Bus. ADDRESS is a host and port, e.g.
+                            127.0.0.1:9375, or the path of a Unix socket.
+    --record SECONDS        Refresh the page frames every SECONDS, also without
+                            subscribers, to record their history. At most a
+                            day. Keeps the server running despite
+                            --idle-timeout.
+    --history-file PATH     Restore the history from PATH at startup and save it
+                            there once a minute and when exiting after
+                            --idle-timeout.
+    --user USER             The unprivileged user to run as after startup.
+                            Defaults to nobody.
+    --group GROUP           The group to run as after startup. Defaults to the
+                            primary group of USER.
+    --workers N             Classify the page frames in N threads. Defaults to
+                            the number of CPUs.
+    -h, --help              Print this help.";
+
+struct Options {
+    idle_timeout: Option<Duration>,
+    export: Option<String>,
+    record_interval: Option<Duration>,
+    history_file: Option<String>,
+    user: String,
+    group: Option<String>,
+    workers: Option<usize>,
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut options = Options {
+            idle_timeout: None,
+            export: None,
+            record_interval: None,
+            history_file: None,
+            user: "nobody".to_string(),
+            group: None,
+            workers: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--idle-timeout" => {
+                    let seconds: u64 = args
+                        .next()
+                        .ok_or("--idle-timeout needs a number of seconds")?
+                        .parse()
+                        .map_err(|err| format!("invalid --idle-timeout: {}", err))?;
+                    options.idle_timeout = Some(Duration::from_secs(seconds))
+                        .filter(|timeout| *timeout > Duration::from_secs(0));
+                }
+                "--export" => {
+                    options.export = Some(args.next().ok_or("--export needs an address")?)
+                }
+                "--record" => {
+                    let seconds: u64 = args
+                        .next()
+                        .ok_or("--record needs a number of seconds")?
+                        .parse()
+                        .map_err(|err| format!("invalid --record: {}", err))?;
+                    let interval = Duration::from_secs(seconds);
+                    if interval > MAX_UPDATE_INTERVAL {
+                        return Err(format!(
+                            "invalid --record: at most {} seconds",
+                            MAX_UPDATE_INTERVAL.as_secs()
+                        ));
+                    }
+                    options.record_interval = Some(interval);
+                }
+                "--history-file" => {
+                    options.history_file = Some(args.next().ok_or("--history-file needs a path")?)
+                }
+                "--user" => options.user = args.next().ok_or("--user needs a user name")?,
+                "--group" => options.group = Some(args.next().ok_or("--group needs a group name")?),
+                "--workers" => {
+                    let workers: usize = args
+                        .next()
+                        .ok_or("--workers needs a number of threads")?
+                        .parse()
+                        .map_err(|err| format!("invalid --workers: {}", err))?;
+                    if workers == 0 {
+                        return Err("--workers needs at least one thread".to_string());
+                    }
+                    options.workers = Some(workers);
+                }
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+        Ok(options)
+    }
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        std::process::exit(2);
+    });
+
     let uid = nix::unistd::getuid();
     if !uid.is_root() {
         return Err("Need root privileges for the meminfo server to run.".into());
     }
 
+    if let Some(address) = &options.export {
+        let listener = Listener::bind(address)
+            .map_err(|err| format!("cannot listen on {}: {}", address, err))?;
+        let mut exporter = Exporter::new(collector(&options)?);
+        // Refreshing needs no capabilities, unlike inspecting processes.
+        privileges::drop_all_privileges(&options.user, options.group.as_deref())?;
+        privileges::restrict_syscalls()?;
+        return Ok(exporter.serve(&listener)?);
+    }
+
     let connection = Connection::new_system()?;
-    let proxy = fdo::DBusProxy::new(&connection)?;
-    proxy.request_name(
-        "de.hpi.felixgohla.meminfo",
-        fdo::RequestNameFlags::ReplaceExisting.into(),
-    )?;
 
     let mut object_server = ObjectServer::new(&connection);
-    let greeter = MeminfoCollector::new().expect("can initialize MeminfoCollector");
-    object_server.at(&"/de/hpi/felixgohla/meminfo".try_into()?, greeter)?;
+    let collector = collector(&options)?;
+    let mut greeter = MeminfoService::new(collector);
+    greeter.set_authority(Authority::system()?);
+    if let Some(interval) = options.record_interval {
+        greeter.set_record_interval(interval);
+    }
+    if let Some(path) = &options.history_file {
+        // Opened before dropping privileges, so that the directory needs no access
+        // for the unprivileged user.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(path)
+            .map_err(|err| format!("cannot open {}: {}", path, err))?;
+        if let Err(err) = greeter.set_history_file(file) {
+            eprintln!("Ignoring the history in {}: {}", path, err);
+        }
+    }
+    object_server.at(&OBJECT_PATH.try_into()?, greeter)?;
 
-    drop_caps()?;
+    privileges::drop_privileges(&options.user, options.group.as_deref())?;
+    privileges::restrict_syscalls()?;
 
-    loop {
-        if let Err(err) = object_server.try_handle_next() {
-            eprintln!("{}", err);
-        }
+    serve(&connection, &mut object_server, options.idle_timeout)
+}
+
+/// Creates a collector for the running kernel, classifying in as many threads as
+/// `options` ask for.
+fn collector(options: &Options) -> io::Result<MeminfoCollector> {
+    let mut collector = MeminfoCollector::new()?;
+    if let Some(workers) = options.workers {
+        collector.set_workers(workers);
     }
+    Ok(collector)
+}
 
-    /* println!("1");
-    let connection = Connection::new_system()?;
-    println!("2");
-    fdo::DBusProxy::new(&connection)?.request_name(
-        "de.hpi.felixgohla.meminfo",
-        fdo::RequestNameFlags::ReplaceExisting.into(),
-    )?;
-    println!("3");
+/// How often the history is saved while the server is running.
+const HISTORY_SAVE_INTERVAL: Duration = Duration::from_secs(60);
 
-    let mut object_server = ObjectServer::new(&connection);
-    let collector = MeminfoCollector { count: 42 };
-    println!("4");
-    object_server.at(&"/de/hpi/felixgohla/meminfo".try_into()?, collector)?;
+/// Matches the signals of the bus announcing that a client disconnected.
+const DISCONNECT_RULE: &str = "type='signal',sender='org.freedesktop.DBus',\
+    interface='org.freedesktop.DBus',member='NameOwnerChanged',arg2=''";
 
-    drop_caps()?;
+/// Handles requests, sends updates to subscribers and saves the history until no
+/// request arrived for `idle_timeout` while there were no subscribers and no
+/// recording.
+///
+/// The loop never blocks on a call of its own, as zbus queues the messages arriving
+/// meanwhile where polling the socket does not see them. The well-known name is
+/// thus requested and released without waiting for the reply, which is picked up
+/// by the loop. In particular, this does not miss the request activating the server,
+/// which the bus sends before the reply to `RequestName`.
+fn serve(
+    connection: &Connection,
+    object_server: &mut ObjectServer,
+    idle_timeout: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    let path = OBJECT_PATH.try_into()?;
+    let flags = fdo::RequestNameFlags::ReplaceExisting | fdo::RequestNameFlags::DoNotQueue;
+    let request_serial = call_bus(connection, "RequestName", &(SERVICE_NAME, flags))?;
+    call_bus(connection, "AddMatch", &DISCONNECT_RULE)?;
+    let mut release_serial = None;
+    let mut last_request = Instant::now();
+    let mut last_save = Instant::now();
 
     loop {
-        if let Err(err) = object_server.try_handle_next() {
-            eprintln!("Error handling request: {}", err);
+        if last_save.elapsed() >= HISTORY_SAVE_INTERVAL {
+            save_history(object_server, &path)?;
+            last_save = Instant::now();
         }
-    }*/
-
-    /*let proxy = AuthorityProxy::new(&connection).unwrap();
-    let subject = Subject::new_for_owner(std::process::id(), None, None).unwrap();
-    let result = proxy.check_authorization(
-        &subject,
-        "de.hpi.felix-gohla.pkexec.meminfo",
-        std::collections::HashMap::new(),
-        CheckAuthorizationFlags::AllowUserInteraction.into(),
-        "",
-    );
-    match result {
-        Ok(result) => {
-            if !result.is_authorized {
-                eprintln!("dafuq");
-                std::process::exit(-1);
+        let next_update = with_service(object_server, &path, |service| service.next_update())?;
+        let now = Instant::now();
+        if next_update.is_some_and(|update| update <= now) {
+            let published =
+                object_server.with(&path, |service: &MeminfoService| service.publish_update());
+            if let Err(err) = published {
+                eprintln!("{}", err);
             }
-            println!("hello from daemon {}", users::get_effective_uid());
-            if users::get_effective_uid() > 0 {
-                std::process::Command::new("/bin/pkexec")
-                    .arg("/home/fg/meminfo/target/debug/meminfo-daemon")
-                    .spawn()
-                    .expect("restart");
-                std::process::exit(0);
+            continue;
+        }
+
+        // Subscribers keep the server running. Once the name is released, the requests
+        // sent to it before are handled before the reply to `ReleaseName` arrives.
+        let idle_deadline = match (idle_timeout, next_update, release_serial) {
+            (Some(idle_timeout), None, None) => Some(last_request + idle_timeout),
+            _ => None,
+        };
+        if idle_deadline.is_some_and(|deadline| deadline <= now) {
+            release_serial = Some(call_bus(connection, "ReleaseName", &SERVICE_NAME)?);
+        } else if let Some(deadline) = next_update.into_iter().chain(idle_deadline).min() {
+            if !wait_readable(connection, deadline - now)? {
+                continue;
             }
         }
-        Err(err) => {
-            eprintln!("auth error: {}", err);
+
+        let message = match object_server.try_handle_next() {
+            Ok(None) => {
+                last_request = Instant::now();
+                continue;
+            }
+            Ok(Some(message)) => message,
+            Err(err) => {
+                eprintln!("{}", err);
+                continue;
+            }
+        };
+        let header = message.header()?;
+        let reply_serial = match header.message_type()? {
+            MessageType::MethodReturn | MessageType::Error => header.reply_serial()?,
+            _ => None,
+        };
+        if reply_serial == Some(request_serial) {
+            check_request_name_reply(message)?;
+        } else if reply_serial.is_some() && reply_serial == release_serial {
+            return save_history(object_server, &path);
+        } else if let Some(name) = disconnected_name(&message)? {
+            with_service(object_server, &path, |service| {
+                service.remove_subscriber(name)
+            })?;
         }
-    }*/
+    }
 }
 
-fn drop_caps() -> Result<(), Box<dyn Error>> {
-    let nobody = User::from_name("nobody")?.expect("nobody user exists");
-    let nogroup = Group::from_name("nogroup")?.expect("nogroup group exists");
-    nix::unistd::setgid(nogroup.gid)?;
-    nix::unistd::setuid(nobody.uid)?;
+/// Saves the history of the service exported at `path`, reporting failures without
+/// stopping the server.
+fn save_history(object_server: &ObjectServer, path: &ObjectPath<'_>) -> Result<(), Box<dyn Error>> {
+    if let Err(err) = with_service(object_server, path, |service| service.save_history())? {
+        eprintln!("cannot save the history: {}", err);
+    }
+    Ok(())
+}
 
-    caps::clear(None, CapSet::Effective)?;
-    caps::clear(None, CapSet::Inheritable)?;
-    caps::clear(None, CapSet::Permitted)?;
+/// Runs `f` on the service exported at `path`.
+fn with_service<T, F>(object_server: &ObjectServer, path: &ObjectPath<'_>, f: F) -> zbus::Result<T>
+where
+    F: Fn(&MeminfoService) -> T,
+{
+    let result = RefCell::new(None);
+    object_server.with(path, |service: &MeminfoService| {
+        *result.borrow_mut() = Some(f(service));
+        Ok(())
+    })?;
+    Ok(result.into_inner().expect("service ran"))
+}
 
-    Ok(())
+/// The name of the client that disconnected if `message` announces that.
+fn disconnected_name(message: &Message) -> zbus::Result<Option<&str>> {
+    let header = message.header()?;
+    if header.message_type()? != MessageType::Signal
+        || header.sender()? != Some("org.freedesktop.DBus")
+        || header.member()? != Some("NameOwnerChanged")
+    {
+        return Ok(None);
+    }
+    let (name, _, new_owner): (&str, &str, &str) = message.body()?;
+    Ok(Some(name).filter(|_| new_owner.is_empty()))
+}
+
+/// Calls `method` of the bus without waiting for the reply, returning the serial
+/// of the call.
+fn call_bus<B>(connection: &Connection, method: &str, body: &B) -> zbus::Result<u32>
+where
+    B: serde::Serialize + zvariant::Type,
+{
+    let call = Message::method(
+        None,
+        Some("org.freedesktop.DBus"),
+        "/org/freedesktop/DBus",
+        Some("org.freedesktop.DBus"),
+        method,
+        body,
+    )?;
+    connection.send_message(call)
+}
+
+fn check_request_name_reply(reply: Message) -> Result<(), Box<dyn Error>> {
+    if reply.header()?.message_type()? == MessageType::Error {
+        return Err(zbus::Error::from(reply).into());
+    }
+    match reply.body::<fdo::RequestNameReply>()? {
+        fdo::RequestNameReply::PrimaryOwner | fdo::RequestNameReply::AlreadyOwner => Ok(()),
+        _ => Err(format!("{} is owned by another process", SERVICE_NAME).into()),
+    }
+}
+
+/// Waits up to `timeout` for a message to arrive on `connection`.
+fn wait_readable(connection: &Connection, timeout: Duration) -> nix::Result<bool> {
+    let mut fds = [PollFd::new(connection.as_raw_fd(), PollFlags::POLLIN)];
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+    match nix::poll::poll(&mut fds, timeout_ms) {
+        Ok(ready) => Ok(ready > 0),
+        Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => Ok(false),
+        Err(err) => Err(err),
+    }
 }
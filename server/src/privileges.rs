@@ -0,0 +1,308 @@
+//! Dropping the privileges of the server once it has opened the kernel's tables.
+
+use std::error::Error;
+use std::io;
+
+use caps::{CapSet, Capability, CapsHashSet};
+use nix::unistd::{Gid, Group, User};
+
+/// The capabilities the server keeps after switching to the unprivileged identity.
+///
+/// They are only permitted, not effective, and raised by [`with_capabilities`] just
+/// for opening the files that need them.
+///
+/// - `CAP_SYS_ADMIN`: the kernel only reveals PFNs in `/proc/PID/pagemap` if the
+///   file was opened with it.
+/// - `CAP_SYS_PTRACE`: reading the pagemap and maps of another user's process
+///   requires ptrace access to it.
+/// - `CAP_DAC_READ_SEARCH`: `/proc/PID/pagemap` is only readable by its owner.
+///
+/// Everything else, including the page frame tables, is opened before switching.
+const RETAINED: [Capability; 3] = [
+    Capability::CAP_SYS_ADMIN,
+    Capability::CAP_SYS_PTRACE,
+    Capability::CAP_DAC_READ_SEARCH,
+];
+
+/// Switches to `user` and `group`, keeping only the [`RETAINED`] capabilities in the
+/// permitted set of the calling thread. Threads spawned afterwards inherit them.
+///
+/// Without `group`, the primary group of `user` is used, as distributions disagree on
+/// whether the group of `nobody` is called `nobody` or `nogroup`. Supplementary
+/// groups are dropped.
+pub fn drop_privileges(user: &str, group: Option<&str>) -> Result<(), Box<dyn Error>> {
+    switch_user(user, group, RETAINED.iter().copied().collect())
+}
+
+/// Switches to `user` and `group` like [`drop_privileges`], but without keeping any
+/// capabilities, for serving nothing but the page frame statistics.
+pub fn drop_all_privileges(user: &str, group: Option<&str>) -> Result<(), Box<dyn Error>> {
+    switch_user(user, group, CapsHashSet::new())
+}
+
+fn switch_user(
+    user: &str,
+    group: Option<&str>,
+    retained: CapsHashSet,
+) -> Result<(), Box<dyn Error>> {
+    let user = User::from_name(user)?.ok_or_else(|| {
+        format!(
+            "user '{}' does not exist, choose another one with --user",
+            user
+        )
+    })?;
+    let gid = match group {
+        Some(group) => {
+            Group::from_name(group)?
+                .ok_or_else(|| {
+                    format!(
+                        "group '{}' does not exist, choose another one with --group",
+                        group
+                    )
+                })?
+                .gid
+        }
+        None => user.gid,
+    };
+    if user.uid.is_root() || gid == Gid::from_raw(0) {
+        return Err("refusing to keep running as root, choose another --user or --group".into());
+    }
+
+    // Dropping from the bounding set requires CAP_SETPCAP, which is gone after the
+    // switch.
+    for cap in caps::read(None, CapSet::Bounding)?.difference(&retained) {
+        caps::drop(None, CapSet::Bounding, *cap)?;
+    }
+    caps::securebits::set_keepcaps(true)?;
+    nix::unistd::setgroups(&[gid])?;
+    nix::unistd::setgid(gid)?;
+    nix::unistd::setuid(user.uid)?;
+    caps::securebits::set_keepcaps(false)?;
+
+    caps::set(None, CapSet::Permitted, &retained)?;
+    caps::clear(None, CapSet::Effective)?;
+    caps::clear(None, CapSet::Inheritable)?;
+    caps::clear(None, CapSet::Ambient)?;
+    Ok(())
+}
+
+/// Runs `f` with the [`RETAINED`] capabilities of the permitted set raised in the
+/// effective set of the calling thread, lowering them again afterwards.
+///
+/// Capabilities that are effective already, e.g. when running as root without
+/// having dropped privileges, are left as they are. If they cannot be raised, `f`
+/// runs anyway and fails on its own.
+pub fn with_capabilities<T, F: FnOnce() -> T>(f: F) -> T {
+    let (effective, permitted) = match (
+        caps::read(None, CapSet::Effective),
+        caps::read(None, CapSet::Permitted),
+    ) {
+        (Ok(effective), Ok(permitted)) => (effective, permitted),
+        _ => return f(),
+    };
+    let raised: CapsHashSet = RETAINED
+        .iter()
+        .filter(|cap| permitted.contains(cap) && !effective.contains(cap))
+        .copied()
+        .collect();
+    if raised.is_empty() || caps::set(None, CapSet::Effective, &(&effective | &raised)).is_err() {
+        return f();
+    }
+    let _lowered = Lowered(effective);
+    f()
+}
+
+/// Restores the effective capabilities when dropped, also when unwinding.
+struct Lowered(CapsHashSet);
+
+impl Drop for Lowered {
+    fn drop(&mut self) {
+        if let Err(err) = caps::set(None, CapSet::Effective, &self.0) {
+            // Going on would leave the capabilities effective for everything the
+            // thread does afterwards.
+            eprintln!("Cannot lower raised capabilities, aborting: {}", err);
+            std::process::abort();
+        }
+    }
+}
+
+/// `struct sock_filter` of classic BPF.
+#[repr(C)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// `struct sock_fprog` of classic BPF.
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+/// `BPF_LD | BPF_W | BPF_ABS`
+const BPF_LD_W_ABS: u16 = 0x20;
+/// `BPF_JMP | BPF_JEQ | BPF_K`
+const BPF_JMP_JEQ_K: u16 = 0x15;
+/// `BPF_JMP | BPF_JGE | BPF_K`
+const BPF_JMP_JGE_K: u16 = 0x35;
+/// `BPF_RET | BPF_K`
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+const SECCOMP_FILTER_FLAG_TSYNC: libc::c_uint = 1;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+/// The offsets of the system call number and architecture in `struct seccomp_data`.
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// System calls on x86_64 with this bit set belong to the x32 ABI.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// The system calls the server never needs once running, which mostly serve to
+/// escalate privileges, e.g. by running programs, changing the identity, loading
+/// code into the kernel or opening files by handle with `CAP_DAC_READ_SEARCH`.
+///
+/// `capset` stays allowed for [`with_capabilities`], as it cannot add capabilities
+/// beyond the permitted set.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_name_to_handle_at,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setfsuid,
+    libc::SYS_setfsgid,
+    libc::SYS_setgroups,
+    libc::SYS_personality,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_acct,
+    libc::SYS_quotactl,
+    libc::SYS_syslog,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_clock_adjtime,
+    libc::SYS_adjtimex,
+    libc::SYS_sethostname,
+    libc::SYS_setdomainname,
+    libc::SYS_fanotify_init,
+    libc::SYS_vhangup,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_iopl,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_ioperm,
+];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const DENIED_SYSCALLS: &[libc::c_long] = &[];
+
+/// Installs a seccomp filter on all threads that fails the [`DENIED_SYSCALLS`]
+/// with `EPERM` and kills the process on system calls of a foreign architecture.
+///
+/// Does nothing on architectures the filter has not been written for.
+pub fn restrict_syscalls() -> io::Result<()> {
+    let arch = match AUDIT_ARCH {
+        Some(arch) => arch,
+        None => {
+            eprintln!("No seccomp filter for this architecture, system calls are not restricted.");
+            return Ok(());
+        }
+    };
+    let statement = |code, k| SockFilter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    let mut filter = vec![
+        statement(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+        SockFilter {
+            code: BPF_JMP_JEQ_K,
+            jt: 1,
+            jf: 0,
+            k: arch,
+        },
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+    ];
+    if cfg!(target_arch = "x86_64") {
+        filter.push(SockFilter {
+            code: BPF_JMP_JGE_K,
+            jt: 0,
+            jf: 1,
+            k: X32_SYSCALL_BIT,
+        });
+        filter.push(statement(BPF_RET_K, deny));
+    }
+    for &syscall in DENIED_SYSCALLS {
+        filter.push(SockFilter {
+            code: BPF_JMP_JEQ_K,
+            jt: 0,
+            jf: 1,
+            k: syscall as u32,
+        });
+        filter.push(statement(BPF_RET_K, deny));
+    }
+    filter.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+
+    let program = SockFprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr(),
+    };
+    // SAFETY: The program outlives both calls, which copy it into the kernel.
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_TSYNC,
+            &program as *const SockFprog,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,337 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+
+use procfs::process::{MMapPath, MemoryMap, Process};
+use procfs::ProcError;
+
+pub use meminfo_common::process::{MappedPagesStats, ProcessStats, VmaStats};
+
+use crate::error::CollectorError;
+use crate::frame_table::PageFrameTable;
+use crate::privileges::with_capabilities;
+use crate::source::{self, CHUNK_FRAMES};
+
+/// The bits of a pagemap entry, see `Documentation/admin-guide/mm/pagemap.rst`.
+const PAGEMAP_PFN: u64 = (1 << 55) - 1;
+const PAGEMAP_SOFT_DIRTY: u64 = 1 << 55;
+const PAGEMAP_EXCLUSIVE: u64 = 1 << 56;
+const PAGEMAP_FILE_OR_SHARED_ANON: u64 = 1 << 61;
+const PAGEMAP_SWAPPED: u64 = 1 << 62;
+const PAGEMAP_PRESENT: u64 = 1 << 63;
+
+/// The entry of a virtual page in `/proc/PID/pagemap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PagemapEntry(pub u64);
+
+impl PagemapEntry {
+    pub fn is_present(self) -> bool {
+        self.0 & PAGEMAP_PRESENT != 0
+    }
+
+    pub fn is_swapped(self) -> bool {
+        self.0 & PAGEMAP_SWAPPED != 0
+    }
+
+    /// Whether the page was written to since the soft-dirty bits of the process were
+    /// last cleared.
+    pub fn is_soft_dirty(self) -> bool {
+        self.0 & PAGEMAP_SOFT_DIRTY != 0
+    }
+
+    /// Whether the page is mapped by this process only, as far as the kernel tracks it.
+    pub fn is_exclusive(self) -> bool {
+        self.0 & PAGEMAP_EXCLUSIVE != 0
+    }
+
+    pub fn is_file_or_shared_anon(self) -> bool {
+        self.0 & PAGEMAP_FILE_OR_SHARED_ANON != 0
+    }
+
+    /// The frame backing the page.
+    ///
+    /// `None` if the page is not present or if the kernel hides PFNs from the reader,
+    /// which it does without `CAP_SYS_ADMIN`.
+    pub fn pfn(self) -> Option<u64> {
+        match self.0 & PAGEMAP_PFN {
+            0 => None,
+            pfn if self.is_present() => Some(pfn),
+            _ => None,
+        }
+    }
+}
+
+/// The page table of a process as exported in `/proc/PID/pagemap`.
+#[derive(Debug)]
+pub struct Pagemap {
+    file: File,
+    page_size: u64,
+}
+
+impl Pagemap {
+    pub fn open(pid: u32, page_size: u64) -> io::Result<Self> {
+        // PFNs are only revealed if the file is opened with CAP_SYS_ADMIN.
+        let file = with_capabilities(|| File::open(format!("/proc/{}/pagemap", pid)))?;
+        Ok(Self { file, page_size })
+    }
+
+    /// Streams the entries of the pages in `addresses` through `f`, together with the
+    /// virtual address of each page.
+    ///
+    /// Streaming stops early if the process exits meanwhile.
+    pub fn for_each_entry<F>(&self, addresses: Range<u64>, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(u64, PagemapEntry),
+    {
+        let mut entries = vec![0u64; CHUNK_FRAMES];
+        let mut page = addresses.start / self.page_size;
+        let end_page = addresses.end.div_ceil(self.page_size);
+        while page < end_page {
+            let wanted = (end_page - page).min(CHUNK_FRAMES as u64) as usize;
+            let read = source::read_entries_at(&self.file, page, &mut entries[..wanted])?;
+            if read == 0 {
+                break;
+            }
+            for (i, &entry) in entries[..read].iter().enumerate() {
+                f((page + i as u64) * self.page_size, PagemapEntry(entry));
+            }
+            page += read as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Walks the VMAs of the process `pid` and joins their pages with `page_frames`.
+pub fn process_stats(
+    pid: u32,
+    page_frames: &PageFrameTable,
+    page_size: u64,
+) -> Result<ProcessStats, CollectorError> {
+    let process =
+        Process::new(pid as i32).map_err(|source| CollectorError::Process { pid, source })?;
+    stats_of(&process, page_frames, page_size)
+}
+
+/// Collects the per-process totals of all processes, leaving out their VMAs.
+///
+/// Processes without resident or swapped pages, e.g. kernel threads, processes
+/// exiting meanwhile and processes the server may not inspect are skipped. The
+/// result is sorted by PID.
+pub fn all_process_stats(
+    page_frames: &PageFrameTable,
+    page_size: u64,
+) -> Result<Vec<ProcessStats>, CollectorError> {
+    let mut all = Vec::new();
+    for_each_process(|process| {
+        let mut stats = stats_of(process, page_frames, page_size)?;
+        if stats.pages.resident + stats.pages.swapped > 0 {
+            stats.vmas = Vec::new();
+            all.push(stats);
+        }
+        Ok(())
+    })?;
+    all.sort_by_key(|stats| stats.pid);
+    Ok(all)
+}
+
+/// The VMAs of `process`, read with the capabilities to inspect processes of other
+/// users.
+pub fn maps(process: &Process) -> Result<Vec<MemoryMap>, CollectorError> {
+    let pid = process.pid as u32;
+    with_capabilities(|| process.maps()).map_err(|source| CollectorError::Process { pid, source })
+}
+
+/// Runs `f` on all processes, skipping processes exiting meanwhile and processes
+/// the server may not inspect.
+pub(crate) fn for_each_process<F>(mut f: F) -> Result<(), CollectorError>
+where
+    F: FnMut(&Process) -> Result<(), CollectorError>,
+{
+    let processes = procfs::process::all_processes()
+        .map_err(|source| CollectorError::Process { pid: 0, source })?;
+    for process in &processes {
+        match f(process) {
+            Err(err) if has_exited(&err) || is_denied(&err) => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+fn stats_of(
+    process: &Process,
+    page_frames: &PageFrameTable,
+    page_size: u64,
+) -> Result<ProcessStats, CollectorError> {
+    let pid = process.pid as u32;
+    let maps = maps(process)?;
+    let pagemap = Pagemap::open(pid, page_size).map_err(CollectorError::io("pagemap"))?;
+
+    let mut stats = ProcessStats {
+        pid,
+        comm: process.stat.comm.clone(),
+        ..Default::default()
+    };
+    for map in maps {
+        let vma = vma_stats(&pagemap, map, page_frames).map_err(CollectorError::io("pagemap"))?;
+        stats.pages.merge(&vma.pages);
+        stats.vmas.push(vma);
+    }
+    Ok(stats)
+}
+
+/// Whether `err` was caused by the process exiting while being inspected.
+fn has_exited(err: &CollectorError) -> bool {
+    match err {
+        CollectorError::Process {
+            source: ProcError::NotFound(_),
+            ..
+        } => true,
+        CollectorError::Io { source, .. } => {
+            source.kind() == io::ErrorKind::NotFound
+                || source.raw_os_error() == Some(nix::errno::Errno::ESRCH as i32)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `err` was caused by the server lacking the permission to inspect a process.
+fn is_denied(err: &CollectorError) -> bool {
+    match err {
+        CollectorError::Process {
+            source: ProcError::PermissionDenied(_),
+            ..
+        } => true,
+        CollectorError::Io { source, .. } => source.kind() == io::ErrorKind::PermissionDenied,
+        _ => false,
+    }
+}
+
+fn vma_stats(
+    pagemap: &Pagemap,
+    map: MemoryMap,
+    page_frames: &PageFrameTable,
+) -> io::Result<VmaStats> {
+    let (start, end) = map.address;
+    let mut pages = MappedPagesStats::default();
+    pagemap.for_each_entry(start..end, |_, entry| {
+        account_page(&mut pages, entry, page_frames)
+    })?;
+    Ok(VmaStats {
+        start,
+        end,
+        perms: map.perms,
+        offset: map.offset,
+        path: describe_path(map.pathname),
+        pages,
+    })
+}
+
+/// Accounts a page of a mapping, looking up its frame in `page_frames`.
+fn account_page(pages: &mut MappedPagesStats, entry: PagemapEntry, page_frames: &PageFrameTable) {
+    if entry.is_swapped() {
+        pages.swapped += 1;
+        return;
+    }
+    if !entry.is_present() {
+        return;
+    }
+    pages.resident += 1;
+    match entry.pfn().and_then(|pfn| page_frames.get(pfn)) {
+        Some(frame) => {
+            if frame.reference_count <= 1 {
+                pages.exclusive += 1;
+            } else {
+                pages.shared += 1;
+            }
+            pages.frames.account(frame.flags);
+        }
+        None => pages.unknown += 1,
+    }
+}
+
+/// Formats the path of a mapping as it appears in `/proc/PID/maps`.
+pub(crate) fn describe_path(path: MMapPath) -> String {
+    match path {
+        MMapPath::Path(path) => path.display().to_string(),
+        MMapPath::Heap => "[heap]".to_string(),
+        MMapPath::Stack => "[stack]".to_string(),
+        MMapPath::TStack(tid) => format!("[stack:{}]", tid),
+        MMapPath::Vdso => "[vdso]".to_string(),
+        MMapPath::Vvar => "[vvar]".to_string(),
+        MMapPath::Vsyscall => "[vsyscall]".to_string(),
+        MMapPath::Anonymous => String::new(),
+        MMapPath::Other(other) => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc_page::{PageCategoryStats, PageFlags};
+    use crate::source::MemorySource;
+    use crate::MeminfoCollector;
+
+    #[test]
+    fn decodes_pagemap_entries_and_accounts_present_pages() {
+        let anon = PageFlags::MMAP | PageFlags::ANON | PageFlags::LRU;
+        let file = PageFlags::MMAP | PageFlags::LRU | PageFlags::ACTIVE;
+        let source = MemorySource::new(
+            vec![1, 1, 3],
+            vec![PageFlags::SLAB.bits(), anon.bits(), file.bits()],
+            4096,
+        )
+        .unwrap();
+        let mut collector = MeminfoCollector::with_source(source);
+        collector.refresh().unwrap();
+
+        let exclusive = PagemapEntry(PAGEMAP_PRESENT | PAGEMAP_EXCLUSIVE | 1);
+        let shared = PagemapEntry(PAGEMAP_PRESENT | PAGEMAP_FILE_OR_SHARED_ANON | 2);
+        let soft_dirty = PagemapEntry(PAGEMAP_PRESENT | PAGEMAP_SOFT_DIRTY | 1);
+        // A frame beyond the table, and a frame hidden from an unprivileged reader.
+        let beyond = PagemapEntry(PAGEMAP_PRESENT | 7);
+        let hidden = PagemapEntry(PAGEMAP_PRESENT);
+        // Swap type 1 at offset 5.
+        let swapped = PagemapEntry(PAGEMAP_SWAPPED | 5 << 5 | 1);
+        let absent = PagemapEntry(2);
+
+        assert_eq!(exclusive.pfn(), Some(1));
+        assert!(exclusive.is_present() && exclusive.is_exclusive());
+        assert!(!exclusive.is_soft_dirty() && !exclusive.is_file_or_shared_anon());
+        assert_eq!(shared.pfn(), Some(2));
+        assert!(shared.is_file_or_shared_anon() && !shared.is_exclusive());
+        assert!(soft_dirty.is_soft_dirty());
+        assert_eq!(soft_dirty.pfn(), Some(1));
+        assert_eq!(beyond.pfn(), Some(7));
+        assert_eq!(hidden.pfn(), None);
+        assert!(swapped.is_swapped() && !swapped.is_present());
+        assert_eq!(swapped.pfn(), None);
+        assert!(!absent.is_present() && !absent.is_swapped());
+        assert_eq!(absent.pfn(), None);
+
+        let mut pages = MappedPagesStats::default();
+        for entry in [
+            exclusive, shared, soft_dirty, beyond, hidden, swapped, absent,
+        ] {
+            account_page(&mut pages, entry, collector.page_frames());
+        }
+        assert_eq!(
+            pages,
+            MappedPagesStats {
+                resident: 5,
+                swapped: 1,
+                exclusive: 2,
+                shared: 1,
+                unknown: 2,
+                frames: PageCategoryStats {
+                    total: 3,
+                    anon: 2,
+                    file: 1,
+                    lru_active: 1,
+                    lru_inactive: 2,
+                    ..Default::default()
+                },
+            }
+        );
+    }
+}
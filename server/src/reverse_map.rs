@@ -0,0 +1,261 @@
+use std::ops::Range;
+
+use crate::error::CollectorError;
+use crate::proc_page::PageFlags;
+use crate::process::{self, Pagemap};
+use crate::source::{self, PageFrameSource, CHUNK_FRAMES};
+
+pub use meminfo_common::reverse_map::{FrameOwner, FrameOwners};
+
+/// The largest number of frames a single query may cover.
+pub const MAX_QUERY_FRAMES: u64 = CHUNK_FRAMES as u64;
+
+/// A VMA of a process that maps at least one frame.
+#[derive(Debug, Clone)]
+struct MappedArea {
+    pid: u32,
+    comm: String,
+    start: u64,
+    end: u64,
+    path: String,
+}
+
+/// A single page mapping a frame.
+#[derive(Debug, Clone, Copy)]
+struct Mapping {
+    pfn: u64,
+    /// The index of the VMA in [`ReverseMap::areas`].
+    area: usize,
+    address: u64,
+}
+
+/// Maps page frames to the pages of all processes mapping them.
+#[derive(Debug, Clone, Default)]
+pub struct ReverseMap {
+    areas: Vec<MappedArea>,
+    /// Sorted by PFN.
+    mappings: Vec<Mapping>,
+}
+
+impl ReverseMap {
+    /// Walks the pagemaps of all processes the server may inspect.
+    ///
+    /// Pages are recorded as of the time their process is walked, so the map of a
+    /// busy system is not an atomic snapshot.
+    pub fn build(page_size: u64) -> Result<Self, CollectorError> {
+        let mut map = Self::default();
+        process::for_each_process(|process| {
+            let pid = process.pid as u32;
+            let maps = process::maps(process)?;
+            let pagemap = Pagemap::open(pid, page_size).map_err(CollectorError::io("pagemap"))?;
+            for vma in maps {
+                let (start, end) = vma.address;
+                let area = map.areas.len();
+                let mapped = map.mappings.len();
+                pagemap
+                    .for_each_entry(start..end, |address, entry| {
+                        if let Some(pfn) = entry.pfn() {
+                            map.mappings.push(Mapping { pfn, area, address });
+                        }
+                    })
+                    .map_err(CollectorError::io("pagemap"))?;
+                if map.mappings.len() > mapped {
+                    map.areas.push(MappedArea {
+                        pid,
+                        comm: process.stat.comm.clone(),
+                        start,
+                        end,
+                        path: process::describe_path(vma.pathname),
+                    });
+                }
+            }
+            Ok(())
+        })?;
+        map.mappings.sort_by_key(|mapping| mapping.pfn);
+        map.areas.shrink_to_fit();
+        map.mappings.shrink_to_fit();
+        Ok(map)
+    }
+
+    /// The number of pages mapping a frame.
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// The pages mapping the frame `pfn`.
+    pub fn owners(&self, pfn: u64) -> impl Iterator<Item = FrameOwner> + '_ {
+        let start = self.mappings.partition_point(|mapping| mapping.pfn < pfn);
+        self.mappings[start..]
+            .iter()
+            .take_while(move |mapping| mapping.pfn == pfn)
+            .map(move |mapping| {
+                let area = &self.areas[mapping.area];
+                FrameOwner {
+                    pid: area.pid,
+                    comm: area.comm.clone(),
+                    vma_start: area.start,
+                    vma_end: area.end,
+                    path: area.path.clone(),
+                    address: mapping.address,
+                }
+            })
+    }
+}
+
+/// Looks up the owners of the present frames in `pfns`, along with their current
+/// flags and reference counts as read from `source`.
+///
+/// At most [`MAX_QUERY_FRAMES`] frames may be queried at once.
+pub fn frame_owners<S>(
+    source: &S,
+    reverse_map: &ReverseMap,
+    pfns: Range<u64>,
+) -> Result<Vec<FrameOwners>, CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let frames = pfns.end.saturating_sub(pfns.start);
+    if frames > MAX_QUERY_FRAMES {
+        return Err(CollectorError::RangeTooLarge {
+            frames,
+            limit: MAX_QUERY_FRAMES,
+        });
+    }
+    let mut owners = Vec::new();
+    source::for_each_chunk(source, pfns, |chunk| {
+        for (i, (&reference_count, &flags)) in chunk.counts.iter().zip(chunk.flags).enumerate() {
+            if PageFlags::from_bits_truncate(flags).contains(PageFlags::NOPAGE) {
+                continue;
+            }
+            let pfn = chunk.start_pfn + i as u64;
+            owners.push(FrameOwners {
+                pfn,
+                flags,
+                reference_count,
+                owners: reverse_map.owners(pfn).collect(),
+            });
+        }
+        Ok(())
+    })?;
+    Ok(owners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+
+    fn area(pid: u32, comm: &str, start: u64, path: &str) -> MappedArea {
+        MappedArea {
+            pid,
+            comm: comm.to_string(),
+            start,
+            end: start + 0x2000,
+            path: path.to_string(),
+        }
+    }
+
+    /// Frame 1 is shared by two processes, frame 3 mapped by one of them only.
+    fn reverse_map() -> ReverseMap {
+        ReverseMap {
+            areas: vec![
+                area(10, "bash", 0x1000, "/bin/bash"),
+                area(20, "cat", 0x8000, ""),
+            ],
+            mappings: vec![
+                Mapping {
+                    pfn: 1,
+                    area: 0,
+                    address: 0x2000,
+                },
+                Mapping {
+                    pfn: 1,
+                    area: 1,
+                    address: 0x8000,
+                },
+                Mapping {
+                    pfn: 3,
+                    area: 1,
+                    address: 0x9000,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn resolves_the_owners_of_present_frames() {
+        let file = PageFlags::MMAP | PageFlags::LRU;
+        let source = MemorySource::new(
+            vec![0, 2, 0, 300],
+            vec![
+                0,
+                file.bits(),
+                PageFlags::NOPAGE.bits(),
+                PageFlags::ANON.bits(),
+            ],
+            4096,
+        )
+        .unwrap();
+
+        let owners = frame_owners(&source, &reverse_map(), 0..4).unwrap();
+        let summary: Vec<_> = owners
+            .iter()
+            .map(|frame| (frame.pfn, frame.flags, frame.reference_count))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, 0, 0),
+                (1, file.bits(), 2),
+                (3, PageFlags::ANON.bits(), 300)
+            ]
+        );
+        assert!(owners[0].owners.is_empty());
+        assert_eq!(
+            owners[1].owners,
+            [
+                FrameOwner {
+                    pid: 10,
+                    comm: "bash".to_string(),
+                    vma_start: 0x1000,
+                    vma_end: 0x3000,
+                    path: "/bin/bash".to_string(),
+                    address: 0x2000,
+                },
+                FrameOwner {
+                    pid: 20,
+                    comm: "cat".to_string(),
+                    vma_start: 0x8000,
+                    vma_end: 0xa000,
+                    path: String::new(),
+                    address: 0x8000,
+                },
+            ]
+        );
+        let pids: Vec<_> = owners[2].owners.iter().map(|owner| owner.pid).collect();
+        assert_eq!(pids, [20]);
+    }
+
+    #[test]
+    fn limits_the_frames_per_query() {
+        let source = MemorySource::new(vec![1; 4], vec![PageFlags::SLAB.bits(); 4], 4096).unwrap();
+        let map = reverse_map();
+
+        assert_eq!(
+            frame_owners(&source, &map, 0..MAX_QUERY_FRAMES)
+                .unwrap()
+                .len(),
+            4
+        );
+        let err = frame_owners(&source, &map, 1..MAX_QUERY_FRAMES + 2).unwrap_err();
+        assert!(matches!(
+            err,
+            CollectorError::RangeTooLarge { frames, limit }
+                if frames == MAX_QUERY_FRAMES + 1 && limit == MAX_QUERY_FRAMES
+        ));
+    }
+}
@@ -0,0 +1,557 @@
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use meminfo_common::INTERFACE_NAME;
+use nix::fcntl::{FcntlArg, OFlag};
+use nix::poll::{PollFd, PollFlags};
+use zbus::{dbus_interface, fdo, MessageHeader, ObjectServer};
+use zvariant::Fd;
+
+use crate::authority::{Action, Authority};
+use crate::cgroup::CgroupStats;
+use crate::error::CollectorError;
+use crate::file_cache::FileCacheStats;
+use crate::history::{History, HistorySample};
+use crate::proc_page::{Anomaly, PageFrameStats};
+use crate::process::ProcessStats;
+use crate::reverse_map::FrameOwners;
+use crate::set_size::{CgroupSetSizes, ProcessSetSizes};
+use crate::snapshot::HostMetadata;
+use crate::source::{PageFrameSource, ProcfsSource};
+use crate::working_set::WorkingSetStats;
+use crate::MeminfoCollector;
+
+/// Subscribers may not ask for updates more often than this, as each update reads
+/// all page frames.
+pub const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longer intervals between updates are lowered to this, a day.
+pub const MAX_UPDATE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The longest interval a working set may be sampled over, five minutes, as the
+/// server handles no other requests meanwhile.
+pub const MAX_WORKING_SET_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A snapshot is abandoned if its reader takes no data for this long, as the server
+/// handles no other requests while writing it.
+pub const SNAPSHOT_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An update every `interval`, the next one at `due`.
+#[derive(Debug, Clone, Copy)]
+struct Schedule {
+    interval: Duration,
+    /// `None` if the next update lies beyond the clock's range, ie. never comes.
+    due: Option<Instant>,
+}
+
+impl Schedule {
+    /// A schedule due right away, with `interval` limited to [`MIN_UPDATE_INTERVAL`]
+    /// and [`MAX_UPDATE_INTERVAL`].
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval: interval.clamp(MIN_UPDATE_INTERVAL, MAX_UPDATE_INTERVAL),
+            due: Some(Instant::now()),
+        }
+    }
+
+    /// Moves the next update to one interval after `now` if it is due before
+    /// `deadline`, returning whether it was.
+    fn advance(&mut self, deadline: Instant, now: Instant) -> bool {
+        let is_due = self.due.is_some_and(|due| due <= deadline);
+        if is_due {
+            self.due = now.checked_add(self.interval);
+        }
+        is_due
+    }
+}
+
+/// The clients subscribed to `StatsUpdated` and the recording of the history, each
+/// on its own schedule.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    /// The schedule of each subscriber, by its unique bus name.
+    subscribers: HashMap<String, Schedule>,
+    /// When the history is recorded without subscribers.
+    recording: Option<Schedule>,
+}
+
+impl Subscriptions {
+    /// When the earliest update is due, `None` without subscribers and recording.
+    fn next_update(&self) -> Option<Instant> {
+        self.subscribers
+            .values()
+            .chain(&self.recording)
+            .filter_map(|schedule| schedule.due)
+            .min()
+    }
+
+    /// Subscribes `name` to updates every `interval_ms` milliseconds, see
+    /// [`Schedule::new`].
+    fn subscribe(&mut self, name: String, interval_ms: u64) {
+        let schedule = Schedule::new(Duration::from_millis(interval_ms));
+        self.subscribers.insert(name, schedule);
+    }
+
+    /// Moves the schedules due before `deadline` to their next update and returns
+    /// the subscribers among them.
+    fn advance(&mut self, deadline: Instant, now: Instant) -> Vec<String> {
+        if let Some(recording) = &mut self.recording {
+            recording.advance(deadline, now);
+        }
+        self.subscribers
+            .iter_mut()
+            .filter_map(|(name, schedule)| schedule.advance(deadline, now).then(|| name.clone()))
+            .collect()
+    }
+}
+
+/// Exports a [`MeminfoCollector`] on D-Bus, checking each call against polkit.
+pub struct MeminfoService<S: PageFrameSource = ProcfsSource> {
+    collector: RefCell<MeminfoCollector<S>>,
+    subscriptions: RefCell<Subscriptions>,
+    /// The statistics of each refresh.
+    history: RefCell<History>,
+    /// Where the history is saved, if anywhere.
+    history_file: Option<File>,
+    /// Whether samples were recorded since the history was last saved.
+    history_changed: Cell<bool>,
+    /// Without one, all calls are denied.
+    authority: Option<Authority>,
+}
+
+impl<S: PageFrameSource> MeminfoService<S> {
+    pub fn new(collector: MeminfoCollector<S>) -> Self {
+        Self {
+            collector: RefCell::new(collector),
+            subscriptions: RefCell::default(),
+            history: RefCell::default(),
+            history_file: None,
+            history_changed: Cell::new(false),
+            authority: None,
+        }
+    }
+
+    /// Sets the authority checking the callers of D-Bus methods.
+    pub fn set_authority(&mut self, authority: Authority) {
+        self.authority = Some(authority);
+    }
+
+    /// Refreshes the page frames every `interval`, also without subscribers, so that
+    /// the history has no gaps. The interval is limited like the ones of subscribers.
+    pub fn set_record_interval(&mut self, interval: Duration) {
+        self.subscriptions.get_mut().recording = Some(Schedule::new(interval));
+    }
+
+    /// Restores the history from `file` and saves it there from now on, see
+    /// [`MeminfoService::save_history`]. An empty file holds an empty history.
+    ///
+    /// If `file` does not contain a history, the history starts empty and the error
+    /// is returned. The file is overwritten by the next save nevertheless.
+    pub fn set_history_file(&mut self, file: File) -> io::Result<()> {
+        let file = self.history_file.insert(file);
+        if file.metadata()?.len() == 0 {
+            return Ok(());
+        }
+        let mut history = History::default();
+        history.read_from(BufReader::new(&*file))?;
+        *self.history.get_mut() = history;
+        Ok(())
+    }
+
+    /// Writes the history to the file set by [`MeminfoService::set_history_file`],
+    /// unless nothing was recorded since the last save.
+    pub fn save_history(&self) -> io::Result<()> {
+        let file = match &self.history_file {
+            Some(file) if self.history_changed.get() => file,
+            _ => return Ok(()),
+        };
+        let mut bytes = Vec::new();
+        self.history.borrow().write(&mut bytes)?;
+        // The file is kept open, as the server may not open it anymore once it has
+        // dropped its privileges.
+        file.write_all_at(&bytes, 0)?;
+        file.set_len(bytes.len() as u64)?;
+        file.sync_data()?;
+        self.history_changed.set(false);
+        Ok(())
+    }
+
+    /// When subscribers are due for the next `StatsUpdated` signal or the history for
+    /// the next sample, `None` without subscribers and recording.
+    pub fn next_update(&self) -> Option<Instant> {
+        self.subscriptions.borrow().next_update()
+    }
+
+    /// Refreshes the page frames, records the statistics in the history and sends
+    /// them in a `StatsUpdated` signal to each subscriber that is due.
+    ///
+    /// Subscribers due within [`MIN_UPDATE_INTERVAL`] are served by this refresh as
+    /// well, so that the page frames are not read more often than that however the
+    /// intervals interleave.
+    ///
+    /// Signals are emitted on the node the object server currently dispatches to, so
+    /// this must be called through [`ObjectServer::with`].
+    pub fn publish_update(&self) -> zbus::Result<()> {
+        let subscribers = {
+            let mut subscriptions = self.subscriptions.borrow_mut();
+            let now = Instant::now();
+            let deadline = now + MIN_UPDATE_INTERVAL;
+            match subscriptions.next_update() {
+                Some(update) if update <= deadline => subscriptions.advance(deadline, now),
+                _ => return Ok(()),
+            }
+        };
+        let (stats, _) = self.refresh().map_err(fdo::Error::from)?;
+        // The signals are sent to each subscriber on its own, so that only callers
+        // authorized to read the statistics receive them.
+        for subscriber in &subscribers {
+            ObjectServer::local_node_emit_signal(
+                Some(subscriber),
+                INTERFACE_NAME,
+                "StatsUpdated",
+                &stats,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Drops the subscription of `name`, e.g. as it disconnected from the bus.
+    pub fn remove_subscriber(&self, name: &str) {
+        self.subscriptions.borrow_mut().subscribers.remove(name);
+    }
+
+    fn authorize(&self, header: &MessageHeader<'_>, action: Action) -> fdo::Result<()> {
+        match &self.authority {
+            Some(authority) => authority.check(header, action),
+            None => Err(fdo::Error::AccessDenied(
+                "the server cannot check authorization".to_string(),
+            )),
+        }
+    }
+
+    /// Refreshes the page frames and records the statistics in the history.
+    fn refresh(&self) -> Result<(PageFrameStats, Vec<Anomaly>), CollectorError> {
+        let (stats, anomalies) = self.collector.borrow_mut().refresh()?;
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.history.borrow_mut().record(HistorySample {
+            timestamp_ms,
+            stats: stats.clone(),
+        });
+        self.history_changed.set(true);
+        Ok((stats, anomalies))
+    }
+
+    /// The collector, refreshed first if there was no refresh yet.
+    fn refreshed(&self) -> fdo::Result<RefMut<'_, MeminfoCollector<S>>> {
+        if self.collector.borrow().page_frames().is_empty() {
+            self.refresh()?;
+        }
+        Ok(self.collector.borrow_mut())
+    }
+}
+
+/// Writes to a file in non-blocking mode, failing once it takes no data for
+/// [`SNAPSHOT_STALL_TIMEOUT`]. The file's flags are restored when dropped, as the
+/// caller shares them.
+struct StallLimitedWriter {
+    file: File,
+    flags: OFlag,
+    /// Set once the timeout passed, so that the writers wrapping this one fail right
+    /// away when flushing on drop.
+    stalled: bool,
+}
+
+impl StallLimitedWriter {
+    fn new(file: File) -> io::Result<Self> {
+        let fd = file.as_raw_fd();
+        let flags = nix::fcntl::fcntl(fd, FcntlArg::F_GETFL).map_err(io::Error::other)?;
+        let flags = OFlag::from_bits_truncate(flags);
+        nix::fcntl::fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            file,
+            flags,
+            stalled: false,
+        })
+    }
+}
+
+impl Write for StallLimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stalled = || io::Error::new(io::ErrorKind::TimedOut, "the reader took no data in time");
+        if self.stalled {
+            return Err(stalled());
+        }
+        loop {
+            match self.file.write(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    let mut fds = [PollFd::new(self.file.as_raw_fd(), PollFlags::POLLOUT)];
+                    let timeout_ms = SNAPSHOT_STALL_TIMEOUT.as_millis() as i32;
+                    match nix::poll::poll(&mut fds, timeout_ms) {
+                        Ok(0) => {
+                            self.stalled = true;
+                            return Err(stalled());
+                        }
+                        Ok(_) | Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+                        Err(err) => return Err(io::Error::other(err)),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for StallLimitedWriter {
+    fn drop(&mut self) {
+        let _ = nix::fcntl::fcntl(self.file.as_raw_fd(), FcntlArg::F_SETFL(self.flags));
+    }
+}
+
+#[dbus_interface(name = "de.hpi.felixgohla.meminfo.meminfo_collector")]
+impl<S: PageFrameSource + 'static> MeminfoService<S> {
+    /// Re-reads all physical page frames and returns statistics about them,
+    /// together with the frames that violated an invariant while being read.
+    fn refresh_physical(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> fdo::Result<(PageFrameStats, Vec<Anomaly>)> {
+        self.authorize(&header, Action::ReadStats)?;
+        Ok(self.refresh()?)
+    }
+
+    /// Sends a `StatsUpdated` signal with the statistics of `RefreshPhysical` to the
+    /// caller every `interval_ms` milliseconds, starting right away, until it calls
+    /// `Unsubscribe` or disconnects.
+    ///
+    /// Each subscriber is signalled at its own interval. Refreshes due within 500 ms
+    /// of each other are shared. Intervals below 500 ms are raised to that, intervals
+    /// above a day lowered to a day. Subscribing again replaces the interval.
+    fn subscribe(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        interval_ms: u64,
+    ) -> fdo::Result<()> {
+        self.authorize(&header, Action::ReadStats)?;
+        let sender = header
+            .sender()
+            .map_err(zbus::Error::from)?
+            .ok_or_else(|| fdo::Error::InvalidArgs("the call has no sender".to_string()))?;
+        self.subscriptions
+            .borrow_mut()
+            .subscribe(sender.to_string(), interval_ms);
+        Ok(())
+    }
+
+    /// Ends the subscription of the caller.
+    fn unsubscribe(&self, #[zbus(header)] header: MessageHeader<'_>) -> fdo::Result<()> {
+        if let Some(sender) = header.sender().map_err(zbus::Error::from)? {
+            self.remove_subscriber(sender);
+        }
+        Ok(())
+    }
+
+    /// Names the page flags the kernel set on any frame during the last refresh.
+    ///
+    /// Flags only meant for kernel hacking appear if the kernel was built to export
+    /// them. Bits unknown to this server are named by their position, e.g. `BIT_43`.
+    fn emitted_flags(&self, #[zbus(header)] header: MessageHeader<'_>) -> fdo::Result<Vec<String>> {
+        self.authorize(&header, Action::ReadStats)?;
+        Ok(self.collector.borrow().emitted_flags())
+    }
+
+    /// Breaks down all physical page frames by the memory cgroup they are charged to.
+    fn cgroup_breakdown(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> fdo::Result<Vec<CgroupStats>> {
+        self.authorize(&header, Action::ReadStats)?;
+        Ok(self.collector.borrow().cgroup_breakdown()?)
+    }
+
+    /// Reports the pages mapped by the process `pid` per VMA, joined with the physical
+    /// page frames backing them.
+    ///
+    /// Frames are looked up in the state of the last refresh, which is done first if
+    /// there was none yet.
+    #[dbus_interface(struct_return)]
+    fn process_map(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        pid: u32,
+    ) -> fdo::Result<ProcessStats> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        Ok(self.refreshed()?.process_stats(pid)?)
+    }
+
+    /// Reports the pages mapped by each process like `ProcessMap`, without the VMAs.
+    fn process_totals(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> fdo::Result<Vec<ProcessStats>> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        Ok(self.refreshed()?.process_totals()?)
+    }
+
+    /// Lists the processes mapping each present frame from `start_pfn` up to, but
+    /// excluding, `end_pfn`, along with the frames' flags and reference counts.
+    fn frame_owners(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        start_pfn: u64,
+        end_pfn: u64,
+    ) -> fdo::Result<Vec<FrameOwners>> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        Ok(self
+            .collector
+            .borrow_mut()
+            .lookup_owners(start_pfn..end_pfn)?)
+    }
+
+    /// Like `FrameOwners`, but for the frames containing the physical addresses from
+    /// `start` up to, but excluding, `end`.
+    fn address_owners(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        start: u64,
+        end: u64,
+    ) -> fdo::Result<Vec<FrameOwners>> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        let mut collector = self.collector.borrow_mut();
+        let page_size = collector.page_size();
+        Ok(collector.lookup_owners(start / page_size..end.div_ceil(page_size))?)
+    }
+
+    /// Computes the RSS, PSS and USS of each process and memory cgroup from the
+    /// frames' reference counts, together with the sizes reported by the kernel in
+    /// smaps_rollup.
+    fn set_sizes(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> fdo::Result<(Vec<ProcessSetSizes>, Vec<CgroupSetSizes>)> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        Ok(self.refreshed()?.set_sizes()?)
+    }
+
+    /// Attributes the file-backed pages mapped by any process to their files and
+    /// reports the resident, dirty and writeback pages per file.
+    fn mapped_file_cache(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> fdo::Result<Vec<FileCacheStats>> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        Ok(self.refreshed()?.mapped_file_cache()?)
+    }
+
+    /// Reports how much of the given files, or the files below the given directories,
+    /// is held in the page cache.
+    ///
+    /// The server may open files the caller may not read, which is why this is
+    /// guarded like the per-process methods.
+    fn probe_file_cache(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        paths: Vec<String>,
+    ) -> fdo::Result<Vec<FileCacheStats>> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        Ok(self.collector.borrow().probe_file_cache(&paths)?)
+    }
+
+    /// Refreshes the page frames and writes a snapshot of them to `fd`, in the format
+    /// described in [`crate::snapshot`].
+    ///
+    /// The server does not handle other requests while writing, so `fd` should be a
+    /// file or a pipe the caller keeps reading from. Writing fails if the pipe takes
+    /// no data for ten seconds. The snapshot contains the flags of every frame, which
+    /// the kernel only reveals to root, so this is guarded like the per-process
+    /// methods.
+    fn dump_snapshot(&self, #[zbus(header)] header: MessageHeader<'_>, fd: Fd) -> fdo::Result<()> {
+        self.authorize(&header, Action::InspectProcesses)?;
+        // The message closes its descriptor when dropped.
+        let fd = nix::unistd::dup(fd.as_raw_fd()).map_err(|err| {
+            fdo::Error::InvalidArgs(format!("cannot use the file descriptor: {}", err))
+        })?;
+        // SAFETY: The duplicated descriptor is owned by nothing else.
+        let file = unsafe { File::from_raw_fd(fd) };
+        let mut collector = self.collector.borrow_mut();
+        let metadata = HostMetadata::current(collector.page_size())
+            .map_err(|err| fdo::Error::IOError(format!("cannot describe the host: {}", err)))?;
+        let writer = StallLimitedWriter::new(file).map_err(|err| {
+            fdo::Error::InvalidArgs(format!("cannot use the file descriptor: {}", err))
+        })?;
+        Ok(collector.write_snapshot(BufWriter::new(writer), &metadata)?)
+    }
+
+    /// Returns the statistics of the refreshes from `start_ms` up to, but excluding,
+    /// `end_ms`, in milliseconds since the Unix epoch, oldest first.
+    ///
+    /// Every refresh is recorded, keeping a sample per second for the last ten
+    /// minutes and a sample per minute for the last day. Older parts of the range are
+    /// thus covered more sparsely. Without subscribers, refreshes only happen on
+    /// request, unless the server was started with `--record`.
+    fn history(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        start_ms: u64,
+        end_ms: u64,
+    ) -> fdo::Result<Vec<HistorySample>> {
+        self.authorize(&header, Action::ReadStats)?;
+        Ok(self.history.borrow().query(start_ms, end_ms))
+    }
+
+    /// Marks all user pages idle, waits for `interval_ms` milliseconds and reports
+    /// how many pages per category, process and cgroup were accessed meanwhile.
+    ///
+    /// The server does not handle other requests while waiting, so callers need a
+    /// call timeout beyond the interval. Intervals above five minutes are rejected.
+    #[dbus_interface(struct_return)]
+    fn estimate_working_set(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        interval_ms: u64,
+    ) -> fdo::Result<WorkingSetStats> {
+        self.authorize(&header, Action::Tune)?;
+        let interval = Duration::from_millis(interval_ms);
+        if interval > MAX_WORKING_SET_INTERVAL {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "the interval may be at most {} ms",
+                MAX_WORKING_SET_INTERVAL.as_millis()
+            )));
+        }
+        Ok(self.refreshed()?.estimate_working_set(interval)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_the_interval_of_subscribers() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.subscribe(":1.1".to_string(), u64::MAX);
+        subscriptions.subscribe(":1.2".to_string(), 0);
+
+        let now = Instant::now();
+        let mut due = subscriptions.advance(now + MIN_UPDATE_INTERVAL, now);
+        due.sort();
+        assert_eq!(due, [":1.1", ":1.2"]);
+        let later = now + MIN_UPDATE_INTERVAL;
+        assert_eq!(subscriptions.next_update(), Some(later));
+
+        subscriptions.subscribers.remove(":1.2");
+        assert_eq!(subscriptions.next_update(), Some(now + MAX_UPDATE_INTERVAL));
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+use procfs::process::Process;
+
+use crate::error::CollectorError;
+use crate::frame_table::{PageFrameTable, SATURATED_REFERENCE_COUNT};
+use crate::privileges::with_capabilities;
+use crate::process::{self, Pagemap, PagemapEntry};
+use crate::source::PageFrameSource;
+
+pub use meminfo_common::set_size::{CgroupSetSizes, ProcessSetSizes, SetSizes};
+
+/// Fixed-point shift for accumulating the proportional share of pages, as done by
+/// the kernel for smaps.
+const PSS_SHIFT: u32 = 12;
+
+/// Sizes from smaps_rollup may deviate by this fraction from the computed ones
+/// before the process is flagged as divergent.
+const DIVERGENCE_TOLERANCE: f64 = 0.05;
+
+/// Sizes from smaps_rollup may always deviate by this number of pages, as the
+/// process keeps running between walking its pagemap and reading smaps_rollup.
+const DIVERGENCE_SLACK_PAGES: u64 = 16;
+
+/// Computes the set sizes of all processes the server may inspect, and sums them
+/// up per memory cgroup.
+///
+/// Frames are looked up in `page_frames`. Reference counts are read anew from
+/// `source` if they are saturated in the table or the frame is not in the table.
+/// Processes are sorted by PID, cgroups by path.
+pub fn set_sizes<S>(
+    source: &S,
+    page_frames: &PageFrameTable,
+) -> Result<(Vec<ProcessSetSizes>, Vec<CgroupSetSizes>), CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let mut processes = Vec::new();
+    process::for_each_process(|process| {
+        let sizes = process_set_sizes(source, page_frames, process)?;
+        if sizes.computed.rss > 0 {
+            processes.push(sizes);
+        }
+        Ok(())
+    })?;
+    processes.sort_by_key(|sizes| sizes.pid);
+
+    let mut cgroups: BTreeMap<&str, CgroupSetSizes> = BTreeMap::new();
+    for sizes in &processes {
+        let cgroup = cgroups
+            .entry(&sizes.cgroup)
+            .or_insert_with(|| CgroupSetSizes {
+                path: sizes.cgroup.clone(),
+                ..Default::default()
+            });
+        cgroup.processes += 1;
+        cgroup.computed.merge(&sizes.computed);
+        cgroup.kernel.merge(&sizes.kernel);
+    }
+    let cgroups = cgroups.into_values().collect();
+    Ok((processes, cgroups))
+}
+
+fn process_set_sizes<S>(
+    source: &S,
+    page_frames: &PageFrameTable,
+    process: &Process,
+) -> Result<ProcessSetSizes, CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let pid = process.pid as u32;
+    let process_error = |source| CollectorError::Process { pid, source };
+    let page_size = source.page_size();
+    let maps = process::maps(process)?;
+    let pagemap = Pagemap::open(pid, page_size).map_err(CollectorError::io("pagemap"))?;
+
+    let mut counter = SetSizeCounter::new(page_size);
+    let mut count_error = None;
+    for map in maps {
+        let (start, end) = map.address;
+        pagemap
+            .for_each_entry(start..end, |_, entry| {
+                if let Err(err) = counter.add(source, page_frames, entry) {
+                    count_error.get_or_insert(err);
+                }
+            })
+            .map_err(CollectorError::io("pagemap"))?;
+    }
+    if let Some(err) = count_error {
+        return Err(CollectorError::io("kpagecount")(err));
+    }
+    let computed = counter.finish();
+
+    let kernel = match smaps_rollup(pid) {
+        Ok(kernel) => kernel,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(CollectorError::io("smaps_rollup")(err)),
+    };
+    let cgroups = process.cgroups().map_err(process_error)?;
+    let cgroup = cgroups
+        .iter()
+        .find(|cgroup| cgroup.controllers.iter().any(|c| c == "memory"))
+        .or_else(|| cgroups.iter().find(|cgroup| cgroup.hierarchy == 0))
+        .map(|cgroup| cgroup.pathname.clone())
+        .unwrap_or_default();
+
+    Ok(ProcessSetSizes {
+        pid,
+        comm: process.stat.comm.clone(),
+        cgroup,
+        divergent: kernel
+            .as_ref()
+            .is_some_and(|kernel| diverges(&computed, kernel, page_size)),
+        computed,
+        kernel: kernel.unwrap_or_default(),
+    })
+}
+
+/// Sums up the set sizes of the pages mapped by a process.
+struct SetSizeCounter {
+    sizes: SetSizes,
+    /// The PSS in bytes, shifted left by [`PSS_SHIFT`].
+    pss: u64,
+    page_size: u64,
+}
+
+impl SetSizeCounter {
+    fn new(page_size: u64) -> Self {
+        Self {
+            sizes: SetSizes::default(),
+            pss: 0,
+            page_size,
+        }
+    }
+
+    /// Accounts the page of `entry` if it is present. Its frame is looked up in
+    /// `page_frames`, the reference count read anew from `source` if it is saturated
+    /// there or the frame is missing.
+    fn add<S>(
+        &mut self,
+        source: &S,
+        page_frames: &PageFrameTable,
+        entry: PagemapEntry,
+    ) -> io::Result<()>
+    where
+        S: PageFrameSource + ?Sized,
+    {
+        if !entry.is_present() {
+            return Ok(());
+        }
+        self.sizes.rss += self.page_size;
+        let pfn = match entry.pfn() {
+            Some(pfn) => pfn,
+            None => return Ok(()),
+        };
+        let reference_count = match page_frames.get(pfn) {
+            Some(frame) if frame.reference_count < SATURATED_REFERENCE_COUNT => {
+                frame.reference_count
+            }
+            _ => exact_reference_count(source, pfn)?,
+        };
+        if reference_count <= 1 {
+            self.sizes.uss += self.page_size;
+            self.pss += self.page_size << PSS_SHIFT;
+        } else {
+            self.pss += (self.page_size << PSS_SHIFT) / reference_count;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> SetSizes {
+        self.sizes.pss = self.pss >> PSS_SHIFT;
+        self.sizes
+    }
+}
+
+/// Whether any of the `computed` sizes deviates from `expected` by more than the
+/// tolerance.
+fn diverges(computed: &SetSizes, expected: &SetSizes, page_size: u64) -> bool {
+    let exceeds = |computed: u64, expected: u64| {
+        let allowed =
+            (expected as f64 * DIVERGENCE_TOLERANCE) as u64 + DIVERGENCE_SLACK_PAGES * page_size;
+        computed.abs_diff(expected) > allowed
+    };
+    exceeds(computed.rss, expected.rss)
+        || exceeds(computed.pss, expected.pss)
+        || exceeds(computed.uss, expected.uss)
+}
+
+/// Reads the reference count of a single frame from `source`.
+fn exact_reference_count<S>(source: &S, pfn: u64) -> io::Result<u64>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let mut count = [0u64];
+    source.read_counts(pfn, &mut count)?;
+    Ok(count[0])
+}
+
+/// Reads the set sizes of the process `pid` from `/proc/PID/smaps_rollup`.
+///
+/// Returns `None` if the process has no address space, e.g. as it is a kernel thread.
+fn smaps_rollup(pid: u32) -> io::Result<Option<SetSizes>> {
+    let rollup = with_capabilities(|| fs::read_to_string(format!("/proc/{}/smaps_rollup", pid)))?;
+    Ok(parse_smaps_rollup(&rollup))
+}
+
+/// Sums the set sizes in the contents of a `smaps_rollup` file, skipping lines of
+/// other forms. Returns `None` for an empty file.
+fn parse_smaps_rollup(rollup: &str) -> Option<SetSizes> {
+    if rollup.is_empty() {
+        return None;
+    }
+    let mut sizes = SetSizes::default();
+    for line in rollup.lines() {
+        let mut fields = line.split_whitespace();
+        let (key, kilobytes) = match (fields.next(), fields.next()) {
+            (Some(key), Some(value)) => match value.parse::<u64>() {
+                Ok(kilobytes) => (key, kilobytes),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        let bytes = kilobytes * 1024;
+        match key {
+            "Rss:" => sizes.rss = bytes,
+            "Pss:" => sizes.pss = bytes,
+            "Private_Clean:" | "Private_Dirty:" => sizes.uss += bytes,
+            _ => {}
+        }
+    }
+    Some(sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc_page::PageFlags;
+    use crate::source::MemorySource;
+    use crate::MeminfoCollector;
+
+    #[test]
+    fn parses_smaps_rollup() {
+        let rollup = "\
+55ecfb739000-7fffd5095000 ---p 00000000 00:00 0                          [rollup]
+Rss:                1304 kB
+Pss:                 356 kB
+Pss_Dirty:           100 kB
+Shared_Clean:       1164 kB
+Shared_Dirty:          0 kB
+Private_Clean:        40 kB
+Private_Dirty:       100 kB
+Referenced:         1304 kB
+Swap:                  0 kB
+";
+        let sizes = parse_smaps_rollup(rollup).unwrap();
+        assert_eq!(sizes.rss, 1304 * 1024);
+        assert_eq!(sizes.pss, 356 * 1024);
+        assert_eq!(sizes.uss, 140 * 1024);
+    }
+
+    #[test]
+    fn parses_empty_smaps_rollup_of_kernel_threads() {
+        assert!(parse_smaps_rollup("").is_none());
+    }
+
+    #[test]
+    fn divides_shared_frames_by_their_exact_reference_count() {
+        let anon = (PageFlags::MMAP | PageFlags::ANON | PageFlags::LRU).bits();
+        // PFN 0 is left unused, as the kernel reports hidden PFNs as 0.
+        let source =
+            MemorySource::new(vec![0, 1, 300, 2], vec![0, anon, anon, anon], 4096).unwrap();
+        let mut collector = MeminfoCollector::with_source(source.clone());
+        collector.refresh().unwrap();
+        let page_frames = collector.page_frames();
+        assert_eq!(
+            page_frames.get(2).unwrap().reference_count,
+            SATURATED_REFERENCE_COUNT
+        );
+
+        let mut counter = SetSizeCounter::new(4096);
+        let present = |pfn: u64| PagemapEntry(1 << 63 | pfn);
+        for entry in [present(1), present(2), present(2), present(2), present(3)] {
+            counter.add(&source, page_frames, entry).unwrap();
+        }
+        // A hidden PFN and an absent page.
+        counter
+            .add(&source, page_frames, PagemapEntry(1 << 63))
+            .unwrap();
+        counter.add(&source, page_frames, PagemapEntry(1)).unwrap();
+
+        // 4096 + 3 * 4096 / 300 + 4096 / 2, where the fractions of a byte add up instead
+        // of being truncated each.
+        let sizes = counter.finish();
+        assert_eq!(sizes.rss, 6 * 4096);
+        assert_eq!(sizes.uss, 4096);
+        assert_eq!(sizes.pss, 6184);
+    }
+}
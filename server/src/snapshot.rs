@@ -0,0 +1,442 @@
+//! A file format preserving what a refresh saw, for analysing it later or on another
+//! machine.
+//!
+//! A snapshot starts with the magic bytes `MEMSNAP\0` and the format version as a
+//! little-endian `u32`. Everything after is a zlib stream of:
+//!
+//! 1. the [`HostMetadata`], as a little-endian `u32` length followed by its D-Bus
+//!    encoding in little-endian byte order,
+//! 2. the [`PageFrameStats`] and anomalies of the refresh, encoded the same way,
+//! 3. a byte telling whether the kpagecgroup table is included,
+//! 4. the page frame tables in chunks of consecutive frames starting at PFN 0. Each
+//!    chunk is the number of frames as a little-endian `u32`, followed by the flags,
+//!    the reference counts and, if included, the cgroup inodes of these frames as
+//!    little-endian `u64`s. A chunk of zero frames ends the stream.
+//!
+//! The tables are copied right after the refresh. As the system keeps running, they
+//! may differ slightly from the statistics stored with them. Classifying the tables
+//! again with [`MeminfoCollector::with_source`](crate::MeminfoCollector::with_source)
+//! and [`Snapshot::source`] gives statistics matching the tables exactly.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::LE;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use zvariant::derive::Type;
+use zvariant::EncodingContext;
+
+use crate::error::CollectorError;
+use crate::proc_page::{Anomaly, PageFrameStats};
+use crate::source::{for_each_chunk, MemorySource, PageFrameSource, CHUNK_FRAMES};
+
+/// The bytes every snapshot starts with.
+pub const MAGIC: &[u8; 8] = b"MEMSNAP\0";
+
+/// The version of the format written by [`write_snapshot`]. Readers reject other
+/// versions.
+pub const VERSION: u32 = 1;
+
+/// Sections larger than this are considered corrupt rather than allocated.
+const MAX_SECTION_SIZE: u32 = 64 << 20;
+
+/// The number of frames [`Snapshot::read`] accepts at most, 16 TiB of 4 KiB pages.
+pub const MAX_FRAMES: u64 = 1 << 32;
+
+/// Describes the machine a snapshot was taken on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct HostMetadata {
+    pub hostname: String,
+    /// The kernel release, e.g. `5.10.0-1-amd64`.
+    pub kernel_release: String,
+    /// The kernel's build version, e.g. `#1 SMP Debian 5.10.4-1 (2020-12-31)`.
+    pub kernel_version: String,
+    /// The hardware architecture, e.g. `x86_64`.
+    pub machine: String,
+    /// The size of a page frame in bytes.
+    pub page_size: u64,
+    /// When the snapshot was taken, in seconds since the Unix epoch.
+    pub taken_at: u64,
+    /// The NUMA nodes, a single one on machines without NUMA.
+    pub nodes: Vec<NumaNode>,
+}
+
+/// A NUMA node and the physical memory attached to it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct NumaNode {
+    pub id: u32,
+    /// The CPUs of the node in the kernel's list format, e.g. `0-3,8-11`.
+    pub cpus: String,
+    pub zones: Vec<Zone>,
+}
+
+/// A memory zone of a NUMA node, as listed in `/proc/zoneinfo`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Type)]
+pub struct Zone {
+    /// The name of the zone, e.g. `DMA32` or `Normal`.
+    pub name: String,
+    /// The first frame of the zone, 0 for empty zones.
+    pub start_pfn: u64,
+    /// The number of frames from the first to the last frame of the zone.
+    pub spanned: u64,
+    /// The number of frames actually present in the zone.
+    pub present: u64,
+}
+
+impl HostMetadata {
+    /// Describes the running machine.
+    pub fn current(page_size: u64) -> io::Result<Self> {
+        let uname = nix::sys::utsname::uname();
+        let taken_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let mut nodes = Vec::new();
+        for (node, zones) in parse_zoneinfo(&fs::read_to_string("/proc/zoneinfo")?)? {
+            let cpus = match fs::read_to_string(format!(
+                "/sys/devices/system/node/node{}/cpulist",
+                node
+            )) {
+                Ok(cpus) => cpus.trim().to_string(),
+                // Kernels without NUMA support have no node directories.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(err),
+            };
+            nodes.push(NumaNode {
+                id: node,
+                cpus,
+                zones,
+            });
+        }
+        Ok(Self {
+            hostname: uname.nodename().to_string(),
+            kernel_release: uname.release().to_string(),
+            kernel_version: uname.version().to_string(),
+            machine: uname.machine().to_string(),
+            page_size,
+            taken_at,
+            nodes,
+        })
+    }
+}
+
+/// Groups the zones listed in `/proc/zoneinfo` by their node.
+///
+/// Each zone starts with a line of the form `Node 0, zone   Normal`, followed by
+/// indented lines like `spanned  786432` or `start_pfn:           1048576`.
+fn parse_zoneinfo(zoneinfo: &str) -> io::Result<Vec<(u32, Vec<Zone>)>> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected zoneinfo line: {}", line),
+        )
+    };
+    let mut nodes: Vec<(u32, Vec<Zone>)> = Vec::new();
+    for line in zoneinfo.lines() {
+        if let Some(zone) = line.strip_prefix("Node ") {
+            let (node, name) = zone.split_once(", zone").ok_or_else(|| invalid(line))?;
+            let node = node.parse().map_err(|_| invalid(line))?;
+            let zone = Zone {
+                name: name.trim().to_string(),
+                start_pfn: 0,
+                spanned: 0,
+                present: 0,
+            };
+            match nodes.last_mut() {
+                Some((last, zones)) if *last == node => zones.push(zone),
+                _ => nodes.push((node, vec![zone])),
+            }
+            continue;
+        }
+        let zone = match nodes.last_mut().and_then(|(_, zones)| zones.last_mut()) {
+            Some(zone) => zone,
+            None => continue,
+        };
+        let mut fields = line.split_whitespace();
+        let field = match fields.next() {
+            Some("start_pfn:") => &mut zone.start_pfn,
+            Some("spanned") => &mut zone.spanned,
+            Some("present") => &mut zone.present,
+            _ => continue,
+        };
+        *field = fields
+            .next()
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| invalid(line))?;
+    }
+    Ok(nodes)
+}
+
+/// Writes a snapshot of the page frame tables of `source` to `writer`, together
+/// with `metadata` and the statistics of the refresh the tables are stored with.
+pub fn write_snapshot<S, W>(
+    mut writer: W,
+    metadata: &HostMetadata,
+    stats: &PageFrameStats,
+    anomalies: &[Anomaly],
+    source: &S,
+) -> Result<(), CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+    W: Write,
+{
+    let write_error = |source| CollectorError::Snapshot { source };
+    writer.write_all(MAGIC).map_err(write_error)?;
+    writer
+        .write_all(&VERSION.to_le_bytes())
+        .map_err(write_error)?;
+
+    let mut encoder = ZlibEncoder::new(writer, Compression::default());
+    write_section(&mut encoder, metadata).map_err(write_error)?;
+    write_section(&mut encoder, &(stats, anomalies)).map_err(write_error)?;
+
+    // The cgroup table is optional, as kernels without memory cgroups lack it.
+    let with_cgroups = match source.read_cgroups(0, &mut [0]) {
+        Ok(_) => true,
+        Err(err) if err.kind() == io::ErrorKind::Unsupported => false,
+        Err(err) => return Err(CollectorError::io("kpagecgroup")(err)),
+    };
+    encoder
+        .write_all(&[with_cgroups as u8])
+        .map_err(write_error)?;
+
+    let mut cgroups = vec![0; if with_cgroups { CHUNK_FRAMES } else { 0 }];
+    for_each_chunk(source, 0..u64::MAX, |chunk| {
+        let frames = chunk.flags.len();
+        encoder
+            .write_all(&(frames as u32).to_le_bytes())
+            .map_err(write_error)?;
+        write_entries(&mut encoder, chunk.flags).map_err(write_error)?;
+        write_entries(&mut encoder, chunk.counts).map_err(write_error)?;
+        if with_cgroups {
+            let read = source
+                .read_cgroups(chunk.start_pfn, &mut cgroups[..frames])
+                .map_err(CollectorError::io("kpagecgroup"))?;
+            if read != frames {
+                return Err(CollectorError::TableLengthMismatch {
+                    table: "kpagecgroup",
+                    entries: chunk.start_pfn + read as u64,
+                    flags: chunk.start_pfn + frames as u64,
+                });
+            }
+            write_entries(&mut encoder, &cgroups[..frames]).map_err(write_error)?;
+        }
+        Ok(())
+    })?;
+    encoder
+        .write_all(&0u32.to_le_bytes())
+        .map_err(write_error)?;
+    encoder
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .map_err(write_error)
+}
+
+pub(crate) fn write_section<W: Write, T: Serialize + zvariant::Type>(
+    writer: &mut W,
+    value: &T,
+) -> io::Result<()> {
+    let bytes = zvariant::to_bytes(EncodingContext::<LE>::new_dbus(0), value)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+fn write_entries<W: Write>(writer: &mut W, entries: &[u64]) -> io::Result<()> {
+    let bytes: Vec<u8> = entries
+        .iter()
+        .flat_map(|entry| entry.to_le_bytes())
+        .collect();
+    writer.write_all(&bytes)
+}
+
+/// A snapshot read back into memory.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub metadata: HostMetadata,
+    /// The statistics of the refresh the snapshot was taken after.
+    pub stats: PageFrameStats,
+    pub anomalies: Vec<Anomaly>,
+    /// Serves the captured page frame tables.
+    pub source: MemorySource,
+}
+
+impl Snapshot {
+    /// Reads a snapshot written by [`write_snapshot`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if `reader` does not contain a
+    /// snapshot of a supported version or it holds more than [`MAX_FRAMES`] frames.
+    pub fn read<R: Read>(reader: R) -> io::Result<Self> {
+        Self::read_limited(reader, MAX_FRAMES)
+    }
+
+    /// Reads a snapshot like [`Snapshot::read`], accepting at most `max_frames`
+    /// frames. The limit is checked before allocating the frames of each chunk.
+    pub fn read_limited<R: Read>(mut reader: R, max_frames: u64) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a meminfo snapshot".to_string()));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported snapshot version {}, expected {}",
+                version, VERSION
+            )));
+        }
+
+        let mut decoder = ZlibDecoder::new(reader);
+        let metadata: HostMetadata = read_section(&mut decoder)?;
+        let (stats, anomalies): (PageFrameStats, Vec<Anomaly>) = read_section(&mut decoder)?;
+        let mut with_cgroups = [0];
+        decoder.read_exact(&mut with_cgroups)?;
+        let with_cgroups = match with_cgroups[0] {
+            0 => false,
+            1 => true,
+            other => return Err(invalid_data(format!("invalid cgroup marker {}", other))),
+        };
+
+        let (mut flags, mut counts, mut cgroups) = (Vec::new(), Vec::new(), Vec::new());
+        let mut total_frames = 0;
+        loop {
+            let frames = read_u32(&mut decoder)? as usize;
+            if frames == 0 {
+                break;
+            }
+            if frames > CHUNK_FRAMES {
+                return Err(invalid_data(format!(
+                    "chunk of {} frames exceeds the limit of {}",
+                    frames, CHUNK_FRAMES
+                )));
+            }
+            total_frames += frames as u64;
+            if total_frames > max_frames {
+                return Err(invalid_data(format!(
+                    "snapshot holds more than {} frames",
+                    max_frames
+                )));
+            }
+            read_entries(&mut decoder, frames, &mut flags)?;
+            read_entries(&mut decoder, frames, &mut counts)?;
+            if with_cgroups {
+                read_entries(&mut decoder, frames, &mut cgroups)?;
+            }
+        }
+
+        let mut source = MemorySource::new(counts, flags, metadata.page_size)?;
+        if with_cgroups {
+            source = source.with_cgroups(cgroups)?;
+        }
+        Ok(Self {
+            metadata,
+            stats,
+            anomalies,
+            source,
+        })
+    }
+}
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_section<R, T>(reader: &mut R) -> io::Result<T>
+where
+    R: Read,
+    T: for<'de> Deserialize<'de> + zvariant::Type,
+{
+    let len = read_u32(reader)?;
+    if len > MAX_SECTION_SIZE {
+        return Err(invalid_data(format!(
+            "section of {} bytes is too large",
+            len
+        )));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    zvariant::from_slice(&bytes, EncodingContext::<LE>::new_dbus(0))
+        .map_err(|err| invalid_data(format!("corrupt section: {}", err)))
+}
+
+fn read_entries<R: Read>(reader: &mut R, frames: usize, table: &mut Vec<u64>) -> io::Result<()> {
+    let mut bytes = vec![0; frames * 8];
+    reader.read_exact(&mut bytes)?;
+    table.extend(bytes.chunks_exact(8).map(|entry| {
+        let mut raw = [0; 8];
+        raw.copy_from_slice(entry);
+        u64::from_le_bytes(raw)
+    }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(name: &str, start_pfn: u64, spanned: u64, present: u64) -> Zone {
+        Zone {
+            name: name.to_string(),
+            start_pfn,
+            spanned,
+            present,
+        }
+    }
+
+    #[test]
+    fn groups_zoneinfo_by_node() {
+        let zoneinfo = "\
+Node 0, zone      DMA
+  per-node stats
+      nr_inactive_anon 1024
+  pages free     3840
+        boost    0
+        spanned  4095
+        present  3998
+        managed  3840
+        protection: (0, 2974, 5932, 5932, 5932)
+  start_pfn:           1
+Node 0, zone   Normal
+  pages free     184467
+        spanned  786432
+        present  786432
+        managed  758676
+  start_pfn:           1048576
+Node 1, zone  Movable
+  pages free     0
+        spanned  0
+        present  0
+        managed  0
+";
+        assert_eq!(
+            parse_zoneinfo(zoneinfo).unwrap(),
+            [
+                (
+                    0,
+                    vec![
+                        zone("DMA", 1, 4095, 3998),
+                        zone("Normal", 1048576, 786432, 786432),
+                    ]
+                ),
+                (1, vec![zone("Movable", 0, 0, 0)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_zoneinfo() {
+        let err = parse_zoneinfo("Node zero, zone DMA\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = parse_zoneinfo("Node 0, zone DMA\n        spanned  many\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
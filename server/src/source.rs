@@ -0,0 +1,411 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::error::CollectorError;
+
+/// The size of a single entry in the kpagecount/kpageflags tables.
+const ENTRY_SIZE: usize = std::mem::size_of::<u64>();
+
+/// Provides the per page frame tables the kernel exports in `/proc/kpagecount`
+/// and `/proc/kpageflags`.
+///
+/// Both tables are indexed by page frame number (PFN) and contain one `u64` per frame.
+/// Reads are positional, so a source can be shared by several readers.
+pub trait PageFrameSource: Sync {
+    /// Reads the reference counts of the frames starting at `pfn` into `buf`.
+    ///
+    /// Returns the number of entries read. `0` means that `pfn` lies beyond the last frame.
+    fn read_counts(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize>;
+
+    /// Reads the raw flags of the frames starting at `pfn` into `buf`.
+    ///
+    /// Returns the number of entries read. `0` means that `pfn` lies beyond the last frame.
+    fn read_flags(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize>;
+
+    /// The size of a page frame in bytes.
+    fn page_size(&self) -> u64;
+
+    /// Reads the inode numbers of the memory cgroups the frames starting at `pfn` are
+    /// charged to, as exported in `/proc/kpagecgroup`.
+    ///
+    /// Returns the number of entries read. `0` means that `pfn` lies beyond the last frame.
+    fn read_cgroups(&self, _pfn: u64, _buf: &mut [u64]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the source has no kpagecgroup table",
+        ))
+    }
+
+    /// The number of free blocks per order as accounted by the buddy allocator,
+    /// if the source knows about them.
+    fn free_blocks(&self) -> io::Result<Option<Vec<u64>>> {
+        Ok(None)
+    }
+
+    /// Marks the user pages among the frames starting at `pfn` as idle, as done by
+    /// writing `/sys/kernel/mm/page_idle/bitmap`.
+    ///
+    /// Bit `i` of `bitmap[j]` refers to the frame `pfn + 64 * j + i`. `pfn` needs to be
+    /// a multiple of 64.
+    fn mark_idle(&self, _pfn: u64, _bitmap: &[u64]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the source does not track idle pages",
+        ))
+    }
+
+    /// Reads which frames starting at `pfn` have not been accessed since they were
+    /// marked idle, laid out as for [`PageFrameSource::mark_idle`].
+    ///
+    /// Returns the number of entries read. `0` means that `pfn` lies beyond the last frame.
+    fn read_idle(&self, _pfn: u64, _bitmap: &mut [u64]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the source does not track idle pages",
+        ))
+    }
+}
+
+/// The number of frames read from a [`PageFrameSource`] at once when streaming.
+pub const CHUNK_FRAMES: usize = 1 << 16;
+
+/// A run of consecutive frames read from both tables of a [`PageFrameSource`].
+#[derive(Debug, Clone, Copy)]
+pub struct FrameChunk<'a> {
+    /// The PFN of the first frame in the chunk.
+    pub start_pfn: u64,
+    pub counts: &'a [u64],
+    pub flags: &'a [u64],
+}
+
+/// Streams the frames in `pfns` through `f`, reading both tables in lock-step.
+///
+/// Only one chunk of each table is held in memory at a time. Streaming stops at the
+/// end of `pfns` or at the last frame of the source, whichever comes first.
+pub fn for_each_chunk<S, F>(source: &S, pfns: Range<u64>, mut f: F) -> Result<(), CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+    F: FnMut(FrameChunk<'_>) -> Result<(), CollectorError>,
+{
+    let mut counts = vec![0u64; CHUNK_FRAMES];
+    let mut flags = vec![0u64; CHUNK_FRAMES];
+    let mut pfn = pfns.start;
+    while pfn < pfns.end {
+        let wanted = (pfns.end - pfn).min(CHUNK_FRAMES as u64) as usize;
+        let counts_read = source
+            .read_counts(pfn, &mut counts[..wanted])
+            .map_err(CollectorError::io("kpagecount"))?;
+        let flags_read = source
+            .read_flags(pfn, &mut flags[..wanted])
+            .map_err(CollectorError::io("kpageflags"))?;
+        if counts_read != flags_read {
+            return Err(CollectorError::TableLengthMismatch {
+                table: "kpagecount",
+                entries: pfn + counts_read as u64,
+                flags: pfn + flags_read as u64,
+            });
+        }
+        if counts_read == 0 {
+            break;
+        }
+        f(FrameChunk {
+            start_pfn: pfn,
+            counts: &counts[..counts_read],
+            flags: &flags[..flags_read],
+        })?;
+        pfn += counts_read as u64;
+    }
+    Ok(())
+}
+
+/// Reads the page frame tables of the running kernel from procfs.
+///
+/// Opening the files requires root privileges (or `CAP_SYS_ADMIN`), reading them
+/// afterwards does not.
+#[derive(Debug)]
+pub struct ProcfsSource {
+    page_count_fd: File,
+    page_flags_fd: File,
+    /// Missing if the kernel has been built without memory cgroups or the file cannot
+    /// be opened.
+    page_cgroup_fd: Option<File>,
+    /// Missing if the kernel has been built without idle page tracking or the bitmap
+    /// cannot be opened for writing, e.g. as sysfs is mounted read-only.
+    page_idle_fd: Option<File>,
+    page_size: u64,
+}
+
+impl ProcfsSource {
+    pub fn new() -> io::Result<Self> {
+        let page_count_fd = File::open("/proc/kpagecount")?;
+        let page_flags_fd = File::open("/proc/kpageflags")?;
+        let page_cgroup_fd = open_optional("/proc/kpagecgroup", OpenOptions::new().read(true));
+        let page_idle_fd = open_optional(
+            "/sys/kernel/mm/page_idle/bitmap",
+            OpenOptions::new().read(true).write(true),
+        );
+        let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+            .map_err(io::Error::other)?
+            .ok_or_else(|| io::Error::other("page size is unknown"))?
+            as u64;
+
+        Ok(Self {
+            page_count_fd,
+            page_flags_fd,
+            page_cgroup_fd,
+            page_idle_fd,
+            page_size,
+        })
+    }
+
+    fn page_idle_file(&self) -> io::Result<&File> {
+        self.page_idle_fd.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "/sys/kernel/mm/page_idle/bitmap is not available",
+            )
+        })
+    }
+}
+
+/// Opens a file the server can do without. If it exists but cannot be opened, the
+/// error is logged and the file treated as missing.
+fn open_optional(path: &str, options: &OpenOptions) -> Option<File> {
+    match options.open(path) {
+        Ok(file) => Some(file),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            eprintln!("Cannot open {}, treating it as unsupported: {}", path, err);
+            None
+        }
+    }
+}
+
+impl PageFrameSource for ProcfsSource {
+    fn read_counts(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize> {
+        read_entries_at(&self.page_count_fd, pfn, buf)
+    }
+
+    fn read_flags(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize> {
+        read_entries_at(&self.page_flags_fd, pfn, buf)
+    }
+
+    fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    fn read_cgroups(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize> {
+        match &self.page_cgroup_fd {
+            Some(file) => read_entries_at(file, pfn, buf),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "/proc/kpagecgroup is not available",
+            )),
+        }
+    }
+
+    fn mark_idle(&self, pfn: u64, bitmap: &[u64]) -> io::Result<()> {
+        let file = self.page_idle_file()?;
+        let bytes = safe_transmute::transmute_to_bytes(bitmap);
+        file.write_all_at(bytes, pfn / 64 * ENTRY_SIZE as u64)
+    }
+
+    fn read_idle(&self, pfn: u64, bitmap: &mut [u64]) -> io::Result<usize> {
+        read_entries_at(self.page_idle_file()?, pfn / 64, bitmap)
+    }
+
+    fn free_blocks(&self) -> io::Result<Option<Vec<u64>>> {
+        let buddyinfo = std::fs::read_to_string("/proc/buddyinfo")?;
+        parse_buddyinfo(&buddyinfo).map(Some)
+    }
+}
+
+/// Sums up the free blocks per order over all zones listed in `/proc/buddyinfo`.
+///
+/// Each line has the form `Node 0, zone   Normal   9690   1934    339 ...`.
+fn parse_buddyinfo(buddyinfo: &str) -> io::Result<Vec<u64>> {
+    let mut blocks: Vec<u64> = Vec::new();
+    for line in buddyinfo.lines() {
+        let counts = line.split_whitespace().skip(4);
+        for (order, count) in counts.enumerate() {
+            let count: u64 = count.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected buddyinfo line: {}", line),
+                )
+            })?;
+            if blocks.len() <= order {
+                blocks.resize(order + 1, 0);
+            }
+            blocks[order] += count;
+        }
+    }
+    Ok(blocks)
+}
+
+/// Reads `u64` entries from a kpage* or pagemap file starting at the entry with
+/// index `index`.
+pub(crate) fn read_entries_at(file: &File, index: u64, buf: &mut [u64]) -> io::Result<usize> {
+    let bytes = safe_transmute::transmute_to_bytes_mut(buf);
+    let offset = index * ENTRY_SIZE as u64;
+    let mut bytes_read = 0;
+    while bytes_read < bytes.len() {
+        match file.read_at(&mut bytes[bytes_read..], offset + bytes_read as u64) {
+            Ok(0) => break,
+            Ok(n) => bytes_read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    if bytes_read % ENTRY_SIZE != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "page frame table ends with a partial entry",
+        ));
+    }
+    Ok(bytes_read / ENTRY_SIZE)
+}
+
+/// Serves page frame tables from memory, e.g. captured kpagecount/kpageflags blobs
+/// of another machine.
+#[derive(Debug, Clone)]
+pub struct MemorySource {
+    counts: Vec<u64>,
+    flags: Vec<u64>,
+    cgroups: Option<Vec<u64>>,
+    page_size: u64,
+}
+
+impl MemorySource {
+    /// Creates a source from already decoded tables.
+    ///
+    /// Both tables need to describe the same number of frames.
+    pub fn new(counts: Vec<u64>, flags: Vec<u64>, page_size: u64) -> io::Result<Self> {
+        if counts.len() != flags.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "kpagecount has {} entries, but kpageflags has {}",
+                    counts.len(),
+                    flags.len()
+                ),
+            ));
+        }
+        Ok(Self {
+            counts,
+            flags,
+            cgroups: None,
+            page_size,
+        })
+    }
+
+    /// Adds a decoded kpagecgroup table, which needs to describe the same number of frames.
+    pub fn with_cgroups(mut self, cgroups: Vec<u64>) -> io::Result<Self> {
+        if cgroups.len() != self.flags.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "kpagecgroup has {} entries, but kpageflags has {}",
+                    cgroups.len(),
+                    self.flags.len()
+                ),
+            ));
+        }
+        self.cgroups = Some(cgroups);
+        Ok(self)
+    }
+
+    /// Creates a source from raw kpagecount and kpageflags contents in native byte order.
+    pub fn from_bytes(counts: &[u8], flags: &[u8], page_size: u64) -> io::Result<Self> {
+        Self::new(decode_table(counts)?, decode_table(flags)?, page_size)
+    }
+
+    /// Creates a source from captured copies of `/proc/kpagecount` and `/proc/kpageflags`.
+    pub fn from_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        counts_path: P,
+        flags_path: Q,
+        page_size: u64,
+    ) -> io::Result<Self> {
+        Self::from_bytes(
+            &std::fs::read(counts_path)?,
+            &std::fs::read(flags_path)?,
+            page_size,
+        )
+    }
+}
+
+impl PageFrameSource for MemorySource {
+    fn read_counts(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize> {
+        Ok(copy_entries(&self.counts, pfn, buf))
+    }
+
+    fn read_flags(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize> {
+        Ok(copy_entries(&self.flags, pfn, buf))
+    }
+
+    fn read_cgroups(&self, pfn: u64, buf: &mut [u64]) -> io::Result<usize> {
+        match &self.cgroups {
+            Some(cgroups) => Ok(copy_entries(cgroups, pfn, buf)),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no kpagecgroup table has been captured",
+            )),
+        }
+    }
+
+    fn page_size(&self) -> u64 {
+        self.page_size
+    }
+}
+
+/// Decodes a raw kpage* table in native byte order.
+pub fn decode_table(bytes: &[u8]) -> io::Result<Vec<u64>> {
+    if bytes.len() % ENTRY_SIZE != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "page frame table ends with a partial entry",
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| {
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw.copy_from_slice(entry);
+            u64::from_ne_bytes(raw)
+        })
+        .collect())
+}
+
+fn copy_entries(table: &[u64], pfn: u64, buf: &mut [u64]) -> usize {
+    let start = (pfn as usize).min(table.len());
+    let end = (start + buf.len()).min(table.len());
+    buf[..end - start].copy_from_slice(&table[start..end]);
+    end - start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_buddyinfo_over_nodes_and_zones() {
+        let buddyinfo = "\
+Node 0, zone      DMA      0      0      0      0      0      0      0      0      1      1      3
+Node 0, zone    DMA32      2      0      1      1      1      2      3      1      0      0    189
+Node 1, zone   Normal   1394   1012    507     58     68     19     10      2      2      0      7
+";
+        assert_eq!(
+            parse_buddyinfo(buddyinfo).unwrap(),
+            [1396, 1012, 508, 59, 69, 21, 13, 3, 3, 1, 199]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_buddyinfo() {
+        let err = parse_buddyinfo("Node 0, zone   Normal   1394   many\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
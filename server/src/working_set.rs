@@ -0,0 +1,196 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::cgroup;
+use crate::error::CollectorError;
+use crate::proc_page::PageFlags;
+use crate::process::{self, Pagemap};
+use crate::source::{self, PageFrameSource, CHUNK_FRAMES};
+
+pub use meminfo_common::working_set::{
+    CgroupWorkingSet, IdleCounts, ProcessWorkingSet, WorkingSetStats,
+};
+
+/// The number of frames covered by one entry of the idle bitmap.
+const FRAMES_PER_ENTRY: u64 = u64::BITS as u64;
+
+/// Marks all user pages up to `end_pfn` idle, waits for `interval` and reports
+/// which of them have been accessed meanwhile.
+///
+/// Marking pages idle clears their accessed bits, so this interferes with other
+/// users of idle page tracking and, slightly, with page reclaim.
+pub fn estimate_working_set<S>(
+    source: &S,
+    end_pfn: u64,
+    interval: Duration,
+) -> Result<WorkingSetStats, CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    mark_all_idle(source, end_pfn)?;
+    thread::sleep(interval);
+    let mut stats = WorkingSetStats {
+        interval_ms: interval.as_millis() as u64,
+        ..Default::default()
+    };
+    let bitmaps = collect_frames(source, end_pfn, &mut stats)?;
+    stats.processes = collect_processes(&bitmaps, source.page_size())?;
+    Ok(stats)
+}
+
+fn mark_all_idle<S>(source: &S, end_pfn: u64) -> Result<(), CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let all_idle = vec![u64::MAX; CHUNK_FRAMES / FRAMES_PER_ENTRY as usize];
+    let mut pfn = 0;
+    while pfn < end_pfn {
+        let entries = (end_pfn - pfn)
+            .div_ceil(FRAMES_PER_ENTRY)
+            .min(all_idle.len() as u64) as usize;
+        source
+            .mark_idle(pfn, &all_idle[..entries])
+            .map_err(CollectorError::io("page_idle bitmap"))?;
+        pfn += entries as u64 * FRAMES_PER_ENTRY;
+    }
+    Ok(())
+}
+
+/// Which frames are tracked, ie. on the LRU lists, and which of those stayed idle.
+struct Bitmaps {
+    tracked: Vec<u64>,
+    idle: Vec<u64>,
+}
+
+impl Bitmaps {
+    fn get(bitmap: &[u64], pfn: u64) -> bool {
+        bitmap
+            .get((pfn / FRAMES_PER_ENTRY) as usize)
+            .is_some_and(|entry| entry & (1 << (pfn % FRAMES_PER_ENTRY)) != 0)
+    }
+
+    /// `None` if the frame is not tracked, otherwise whether it stayed idle.
+    fn is_idle(&self, pfn: u64) -> Option<bool> {
+        if Self::get(&self.tracked, pfn) {
+            Some(Self::get(&self.idle, pfn))
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the idle bitmap and accounts the tracked frames per category and cgroup.
+fn collect_frames<S>(
+    source: &S,
+    end_pfn: u64,
+    stats: &mut WorkingSetStats,
+) -> Result<Bitmaps, CollectorError>
+where
+    S: PageFrameSource + ?Sized,
+{
+    let entries = end_pfn.div_ceil(FRAMES_PER_ENTRY) as usize;
+    let mut bitmaps = Bitmaps {
+        tracked: vec![0; entries],
+        idle: vec![0; entries],
+    };
+    let mut read = 0;
+    while read < entries {
+        let n = source
+            .read_idle(read as u64 * FRAMES_PER_ENTRY, &mut bitmaps.idle[read..])
+            .map_err(CollectorError::io("page_idle bitmap"))?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+
+    let mut cgroups = vec![0u64; CHUNK_FRAMES];
+    let mut by_inode: Option<HashMap<u64, IdleCounts>> = Some(HashMap::new());
+    source::for_each_chunk(source, 0..end_pfn, |chunk| {
+        let cgroups: &[u64] = if by_inode.is_some() {
+            match source.read_cgroups(chunk.start_pfn, &mut cgroups[..chunk.flags.len()]) {
+                Ok(read) => &cgroups[..read],
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                    by_inode = None;
+                    &[]
+                }
+                Err(err) => return Err(CollectorError::io("kpagecgroup")(err)),
+            }
+        } else {
+            &[]
+        };
+        for (i, &raw_flags) in chunk.flags.iter().enumerate() {
+            let flags = PageFlags::from_bits_truncate(raw_flags);
+            if flags.contains(PageFlags::NOPAGE) || !flags.contains(PageFlags::LRU) {
+                continue;
+            }
+            let pfn = chunk.start_pfn + i as u64;
+            bitmaps.tracked[(pfn / FRAMES_PER_ENTRY) as usize] |= 1 << (pfn % FRAMES_PER_ENTRY);
+            let idle = Bitmaps::get(&bitmaps.idle, pfn);
+            if idle {
+                stats.idle.account(flags);
+            } else {
+                stats.accessed.account(flags);
+            }
+            if let (Some(by_inode), Some(&inode)) = (&mut by_inode, cgroups.get(i)) {
+                by_inode.entry(inode).or_default().account(idle);
+            }
+        }
+        Ok(())
+    })?;
+
+    if let Some(by_inode) = by_inode {
+        let paths = cgroup::cgroup_paths(Path::new(cgroup::CGROUP2_ROOT))
+            .map_err(CollectorError::io("cgroup hierarchy"))?;
+        stats.cgroups = by_inode
+            .into_iter()
+            .map(|(inode, pages)| CgroupWorkingSet {
+                inode,
+                path: paths.get(&inode).cloned().unwrap_or_default(),
+                pages,
+            })
+            .collect();
+        stats
+            .cgroups
+            .sort_by_key(|cgroup| Reverse(cgroup.pages.idle));
+    }
+    Ok(bitmaps)
+}
+
+/// Looks up the tracked frames mapped by each process in the bitmaps.
+fn collect_processes(
+    bitmaps: &Bitmaps,
+    page_size: u64,
+) -> Result<Vec<ProcessWorkingSet>, CollectorError> {
+    let mut processes = Vec::new();
+    process::for_each_process(|process| {
+        let pid = process.pid as u32;
+        let maps = process::maps(process)?;
+        let pagemap = Pagemap::open(pid, page_size).map_err(CollectorError::io("pagemap"))?;
+        let mut pages = IdleCounts::default();
+        for map in maps {
+            let (start, end) = map.address;
+            pagemap
+                .for_each_entry(start..end, |_, entry| {
+                    if let Some(idle) = entry.pfn().and_then(|pfn| bitmaps.is_idle(pfn)) {
+                        pages.account(idle);
+                    }
+                })
+                .map_err(CollectorError::io("pagemap"))?;
+        }
+        if pages.accessed + pages.idle > 0 {
+            processes.push(ProcessWorkingSet {
+                pid,
+                comm: process.stat.comm.clone(),
+                pages,
+            });
+        }
+        Ok(())
+    })?;
+    processes.sort_by_key(|process| Reverse(process.pages.idle));
+    Ok(processes)
+}
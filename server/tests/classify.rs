@@ -0,0 +1,237 @@
+use meminfo_server::classify::{MAX_REPORTED_ANOMALIES, RANGE_FRAMES};
+use meminfo_server::proc_page::{CompoundOrderStats, Invariant, PageFlags, PageFrameStats};
+use meminfo_server::source::MemorySource;
+use meminfo_server::MeminfoCollector;
+
+/// A collector for the frames of `frames`, given by their flags and reference count
+/// starting at PFN 0.
+fn collector(frames: &[(PageFlags, u64)]) -> MeminfoCollector<MemorySource> {
+    let flags = frames.iter().map(|(flags, _)| flags.bits()).collect();
+    let counts = frames.iter().map(|(_, count)| *count).collect();
+    MeminfoCollector::with_source(MemorySource::new(counts, flags, 4096).unwrap())
+}
+
+fn classify(frames: &[(PageFlags, u64)]) -> PageFrameStats {
+    collector(frames).refresh().unwrap().0
+}
+
+const UNUSED: (PageFlags, u64) = (PageFlags::empty(), 0);
+const USED: (PageFlags, u64) = (PageFlags::SLAB, 1);
+const BUDDY: (PageFlags, u64) = (PageFlags::BUDDY, 0);
+
+/// `count` frames like `frame`.
+fn repeat(frame: (PageFlags, u64), count: usize) -> Vec<(PageFlags, u64)> {
+    vec![frame; count]
+}
+
+#[test]
+fn adjacent_buddy_heads_start_their_own_blocks() {
+    // An order-0 block at PFN 7 and an order-3 block at PFN 8.
+    let mut frames = repeat(USED, 7);
+    frames.push(BUDDY);
+    frames.push(BUDDY);
+    frames.extend(repeat(UNUSED, 7));
+    frames.push(USED);
+
+    let stats = classify(&frames);
+    assert_eq!(stats.buddy_stats.blocks, [1, 0, 0, 1]);
+    assert_eq!(stats.buddy, 2);
+}
+
+#[test]
+fn adjacent_blocks_with_every_frame_flagged() {
+    // As reported by newer kernels, the order-3 block at PFN 8 is flagged throughout.
+    let mut frames = repeat(USED, 7);
+    frames.extend(repeat(BUDDY, 9));
+    frames.push(USED);
+
+    let stats = classify(&frames);
+    assert_eq!(stats.buddy_stats.blocks, [1, 0, 0, 1]);
+}
+
+#[test]
+fn free_block_stops_at_its_alignment() {
+    // A block at PFN 2 spans at most two frames, the unused frames after belong to
+    // no block.
+    let mut frames = repeat(USED, 2);
+    frames.push(BUDDY);
+    frames.extend(repeat(UNUSED, 5));
+
+    let stats = classify(&frames);
+    assert_eq!(stats.buddy_stats.blocks, [0, 1]);
+}
+
+#[test]
+fn free_block_ends_at_a_used_frame() {
+    // The three frames before the used one are split into an order-1 block at PFN 0
+    // and an order-0 block at PFN 2.
+    let mut frames = vec![BUDDY];
+    frames.extend(repeat(UNUSED, 2));
+    frames.push(USED);
+
+    let stats = classify(&frames);
+    assert_eq!(stats.buddy_stats.blocks, [1, 1]);
+}
+
+#[test]
+fn buddy_head_after_unflagged_frames_starts_its_own_block() {
+    // An order-2 block at PFN 0 and an order-1 block at PFN 4, which lies within the
+    // alignment of the first.
+    let mut frames = vec![BUDDY];
+    frames.extend(repeat(UNUSED, 3));
+    frames.push(BUDDY);
+    frames.push(UNUSED);
+
+    let stats = classify(&frames);
+    assert_eq!(stats.buddy_stats.blocks, [0, 1, 1]);
+}
+
+#[test]
+fn free_block_is_limited_to_the_highest_order() {
+    let mut frames = vec![BUDDY];
+    frames.extend(repeat(UNUSED, (2 << 10) - 1));
+
+    let stats = classify(&frames);
+    assert_eq!(stats.buddy_stats.blocks, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+}
+
+/// A compound page of `order` whose frames carry `flags` besides the compound flags.
+/// Its frames are unreferenced, so that frames without other flags do not violate
+/// `UnusedIsUnreferenced`.
+fn compound(flags: PageFlags, order: u32) -> Vec<(PageFlags, u64)> {
+    let mut frames = vec![(flags | PageFlags::COMPOUND_HEAD, 0)];
+    frames.extend(repeat(
+        (flags | PageFlags::COMPOUND_TAIL, 0),
+        (1 << order) - 1,
+    ));
+    frames
+}
+
+fn orders(order: u32, count: u64) -> Vec<CompoundOrderStats> {
+    vec![CompoundOrderStats {
+        order,
+        count,
+        bytes: count << (order + 12),
+    }]
+}
+
+#[test]
+fn groups_compound_pages_by_owner() {
+    let mut frames = compound(PageFlags::HUGE, 1);
+    frames.extend(compound(
+        PageFlags::THP | PageFlags::ANON | PageFlags::MMAP,
+        2,
+    ));
+    frames.extend(compound(PageFlags::SLAB, 1));
+    // Heads directly following each other start their own pages.
+    frames.extend(compound(PageFlags::empty(), 0));
+    frames.extend(compound(PageFlags::empty(), 1));
+    frames.push(USED);
+
+    let stats = classify(&frames);
+    let compounds = &stats.compound_stats;
+    assert_eq!(compounds.total, 5);
+    assert_eq!(compounds.total_frames, 11);
+    assert_eq!(compounds.huge_tlb, orders(1, 1));
+    assert_eq!(compounds.transparent, orders(2, 1));
+    assert_eq!(compounds.slab, orders(1, 1));
+    assert_eq!(compounds.other, [orders(0, 1), orders(1, 1)].concat());
+    assert_eq!(stats.huge_stats.reserved, 1);
+    assert_eq!(stats.huge_stats.transparent, 1);
+    assert_eq!(stats.huge_stats.transparent_fine_granular, 4);
+    assert_eq!(stats.anomalies, 0);
+}
+
+#[test]
+fn reports_each_violated_invariant() {
+    let lru = PageFlags::LRU | PageFlags::MMAP;
+    let mut frames = vec![
+        (lru | PageFlags::ACTIVE | PageFlags::UNEVICTABLE, 1),
+        (PageFlags::KSM, 1),
+        (PageFlags::REFERENCED, 1),
+        (PageFlags::COMPOUND_TAIL, 0),
+    ];
+    frames.extend(compound(PageFlags::empty(), 2));
+    // Cuts the compound page at PFN 4 down to three frames.
+    frames[7] = USED;
+
+    let (stats, anomalies) = collector(&frames).refresh().unwrap();
+    let reported: Vec<_> = anomalies
+        .iter()
+        .map(|anomaly| (anomaly.pfn, anomaly.invariant))
+        .collect();
+    assert_eq!(
+        reported,
+        [
+            (0, Invariant::ActiveIsEvictable),
+            (1, Invariant::KsmIsShared),
+            (2, Invariant::UnusedIsUnreferenced),
+            (3, Invariant::TailFollowsHead),
+            (4, Invariant::CompoundIsPowerOfTwo),
+        ]
+    );
+    assert_eq!(stats.anomalies, 5);
+    assert_eq!(anomalies[1].flags, PageFlags::KSM.bits());
+    assert_eq!(anomalies[1].reference_count, 1);
+}
+
+#[test]
+fn counts_anomalies_beyond_the_reported_ones() {
+    let orphans = repeat((PageFlags::COMPOUND_TAIL, 0), MAX_REPORTED_ANOMALIES + 10);
+
+    let (stats, anomalies) = collector(&orphans).refresh().unwrap();
+    assert_eq!(stats.anomalies, MAX_REPORTED_ANOMALIES as u64 + 10);
+    assert_eq!(anomalies.len(), MAX_REPORTED_ANOMALIES);
+}
+
+/// A bit more than two ranges of frames of all kinds. A compound page ends and a
+/// free block starts right at the boundary between the first two ranges.
+fn two_ranges() -> Vec<(PageFlags, u64)> {
+    let range_frames = RANGE_FRAMES as usize;
+    let anon = PageFlags::MMAP | PageFlags::ANON | PageFlags::LRU;
+    let mixed = [
+        (anon, 1),
+        (PageFlags::MMAP | PageFlags::LRU | PageFlags::ACTIVE, 2),
+        USED,
+        UNUSED,
+        (PageFlags::REFERENCED, 0),
+        (PageFlags::PAGETABLE, 1),
+        BUDDY,
+        (PageFlags::KSM, 1),
+    ];
+    let mut frames: Vec<_> = (0..2 * range_frames + 1000)
+        .map(|pfn| mixed[pfn % mixed.len()])
+        .collect();
+
+    let head = (anon | PageFlags::COMPOUND_HEAD | PageFlags::THP, 1);
+    let tail = (anon | PageFlags::COMPOUND_TAIL | PageFlags::THP, 1);
+    let compound = range_frames - 512;
+    frames[compound] = head;
+    frames[compound + 1..range_frames].fill(tail);
+    frames[range_frames] = BUDDY;
+    frames[range_frames + 1..range_frames + 1024].fill(UNUSED);
+    frames[range_frames + 1024] = USED;
+    // A tail without its head in the second range.
+    frames[range_frames + 2048] = tail;
+    frames
+}
+
+#[test]
+fn several_workers_classify_like_one() {
+    let frames = two_ranges();
+    let mut single = collector(&frames);
+    single.set_workers(1);
+    let (stats, anomalies) = single.refresh().unwrap();
+    assert_eq!(stats.compound_stats.transparent[0].order, 9);
+    assert_eq!(stats.buddy_stats.blocks[10], 1);
+    assert!(stats.anomalies > 0);
+
+    for workers in [2, 3, 8] {
+        let mut parallel = collector(&frames);
+        parallel.set_workers(workers);
+        let (parallel_stats, parallel_anomalies) = parallel.refresh().unwrap();
+        assert_eq!(parallel_stats, stats, "{} workers", workers);
+        assert_eq!(parallel_anomalies, anomalies, "{} workers", workers);
+        assert_eq!(parallel.page_frames(), single.page_frames());
+    }
+}
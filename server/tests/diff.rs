@@ -0,0 +1,136 @@
+use meminfo_server::diff::{diff_frames, ChangedRange};
+use meminfo_server::frame_table::PageFrameTable;
+use meminfo_server::proc_page::{FrameCategory, LruList, PageFlags};
+
+/// A table of the frames with `flags`, starting at PFN 0. Frames with the `NOPAGE`
+/// flag are left out.
+fn table(flags: &[PageFlags]) -> PageFrameTable {
+    let mut table = PageFrameTable::new();
+    for (pfn, flags) in flags.iter().enumerate() {
+        if !flags.contains(PageFlags::NOPAGE) {
+            table.push(pfn as u64, flags.bits(), 1);
+        }
+    }
+    table
+}
+
+fn flag_name(flag: PageFlags) -> String {
+    PageFlags::names(flag.bits()).remove(0)
+}
+
+#[test]
+fn counts_transitions_between_categories_and_lists() {
+    let anon = PageFlags::MMAP | PageFlags::ANON | PageFlags::LRU;
+    let file = PageFlags::MMAP | PageFlags::LRU;
+    let before = table(&[
+        anon,
+        file,
+        PageFlags::empty(),
+        PageFlags::empty(),
+        PageFlags::SLAB,
+        PageFlags::NOPAGE,
+    ]);
+    let after = table(&[
+        anon | PageFlags::ACTIVE,
+        PageFlags::empty(),
+        PageFlags::SLAB,
+        PageFlags::SLAB,
+        PageFlags::NOPAGE,
+        PageFlags::empty(),
+    ]);
+
+    let diff = diff_frames(&before, &after);
+    let (anon, file, slab, free) = (
+        Some(FrameCategory::Anon),
+        Some(FrameCategory::File),
+        Some(FrameCategory::Slab),
+        Some(FrameCategory::Free),
+    );
+    assert_eq!(diff.category_frames(anon, anon), 1);
+    assert_eq!(diff.category_frames(file, free), 1);
+    assert_eq!(diff.category_frames(free, slab), 2);
+    assert_eq!(diff.category_frames(slab, None), 1);
+    assert_eq!(diff.category_frames(None, free), 1);
+    assert_eq!(diff.category_frames(free, free), 0);
+    let largest = diff.category_changes()[0];
+    assert_eq!((largest.from, largest.to, largest.frames), (free, slab, 2));
+    assert_eq!(diff.category_changes().len(), 4);
+
+    let (inactive, active) = (Some(LruList::Inactive), Some(LruList::Active));
+    assert_eq!(diff.lru_frames(inactive, active), 1);
+    assert_eq!(diff.lru_frames(inactive, None), 1);
+    assert_eq!(diff.lru_frames(None, None), 4);
+
+    let flags: Vec<_> = diff
+        .flags
+        .iter()
+        .map(|change| (change.flag.clone(), change.gained, change.lost))
+        .collect();
+    assert_eq!(
+        flags,
+        [
+            (flag_name(PageFlags::LRU), 0, 1),
+            (flag_name(PageFlags::ACTIVE), 1, 0),
+            (flag_name(PageFlags::SLAB), 2, 0),
+            (flag_name(PageFlags::MMAP), 0, 1),
+        ]
+    );
+}
+
+#[test]
+fn joins_changed_frames_into_ranges() {
+    let before = table(&[
+        PageFlags::SLAB,
+        PageFlags::SLAB,
+        PageFlags::SLAB,
+        PageFlags::SLAB,
+        PageFlags::empty(),
+        PageFlags::SLAB,
+    ]);
+    let after = table(&[
+        PageFlags::SLAB,
+        PageFlags::empty(),
+        PageFlags::empty(),
+        PageFlags::BUDDY,
+        PageFlags::SLAB,
+        PageFlags::SLAB,
+        PageFlags::empty(),
+    ]);
+
+    let diff = diff_frames(&before, &after);
+    let range = |start_pfn, end_pfn, from, to| ChangedRange {
+        start_pfn,
+        end_pfn,
+        from,
+        to,
+    };
+    let (slab, free, buddy) = (
+        Some(FrameCategory::Slab),
+        Some(FrameCategory::Free),
+        Some(FrameCategory::Buddy),
+    );
+    assert_eq!(
+        diff.changed_ranges,
+        [
+            range(1, 3, slab, free),
+            range(3, 4, slab, buddy),
+            range(4, 5, free, slab),
+            range(6, 7, None, free),
+        ]
+    );
+}
+
+#[test]
+fn finds_no_changes_between_equal_tables() {
+    let frames = table(&[PageFlags::SLAB, PageFlags::NOPAGE, PageFlags::BUDDY]);
+
+    let diff = diff_frames(&frames, &frames);
+    assert!(diff.category_changes().is_empty());
+    assert!(diff.lru_changes().is_empty());
+    assert!(diff.flags.is_empty());
+    assert!(diff.changed_ranges.is_empty());
+    assert_eq!(
+        diff.category_frames(Some(FrameCategory::Slab), Some(FrameCategory::Slab)),
+        1
+    );
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+use meminfo_server::exporter::{self, Exporter, Listener, CONTENT_TYPE};
+use meminfo_server::proc_page::PageFlags;
+use meminfo_server::source::MemorySource;
+use meminfo_server::MeminfoCollector;
+
+/// The samples of a parsed exposition, by metric name and sorted labels.
+type Samples = HashMap<String, BTreeMap<Vec<(String, String)>, f64>>;
+
+/// Parses the OpenMetrics text format as far as the exporter uses it, asserting
+/// that it is well-formed: every family is declared once as a gauge before its
+/// samples, sample names match their family, label sets are unique and the
+/// exposition ends with `# EOF`.
+fn parse(text: &str) -> Samples {
+    let body = text
+        .strip_suffix("# EOF\n")
+        .expect("exposition ends with # EOF");
+    let mut samples = Samples::new();
+    let mut family: Option<&str> = None;
+    for line in body.lines() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            let mut parts = comment.splitn(3, ' ');
+            let (kind, name) = (parts.next().unwrap(), parts.next().unwrap());
+            match kind {
+                "TYPE" => {
+                    assert_eq!(parts.next(), Some("gauge"), "{}", line);
+                    assert!(
+                        samples.insert(name.to_string(), BTreeMap::new()).is_none(),
+                        "{} declared twice",
+                        name
+                    );
+                    family = Some(name);
+                }
+                "HELP" => assert_eq!(family, Some(name), "{}", line),
+                _ => panic!("unexpected comment {}", line),
+            }
+            continue;
+        }
+
+        let (name, labels, value) = match line.find('{') {
+            Some(open) => {
+                let close = line.find("} ").expect("labels are closed");
+                (
+                    &line[..open],
+                    parse_labels(&line[open + 1..close]),
+                    &line[close + 2..],
+                )
+            }
+            None => {
+                let (name, value) = line.split_once(' ').expect("sample has a value");
+                (name, Vec::new(), value)
+            }
+        };
+        assert_eq!(Some(name), family, "sample outside its family: {}", line);
+        let value = value.parse().expect("value is a number");
+        let family = samples.get_mut(name).unwrap();
+        assert!(
+            family.insert(labels, value).is_none(),
+            "duplicate sample {}",
+            line
+        );
+    }
+    samples
+}
+
+fn parse_labels(labels: &str) -> Vec<(String, String)> {
+    let mut labels: Vec<_> = labels
+        .split(',')
+        .map(|label| {
+            let (name, value) = label.split_once('=').expect("label has a value");
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .expect("label value is quoted");
+            (name.to_string(), value.to_string())
+        })
+        .collect();
+    labels.sort();
+    labels
+}
+
+fn frames(samples: &Samples, category: &str, subcategory: &str) -> f64 {
+    let labels = vec![
+        ("category".to_string(), category.to_string()),
+        ("subcategory".to_string(), subcategory.to_string()),
+    ];
+    samples["meminfo_page_frames"][&labels]
+}
+
+/// A small machine with a few frames of each kind and a free block of order 2.
+fn collector() -> MeminfoCollector<MemorySource> {
+    let anon = PageFlags::MMAP | PageFlags::ANON | PageFlags::LRU | PageFlags::ACTIVE;
+    let file = PageFlags::MMAP | PageFlags::LRU | PageFlags::UPTODATE;
+    let frames = [
+        (anon, 1),
+        (anon, 1),
+        (file, 2),
+        (PageFlags::SLAB, 1),
+        (PageFlags::BUDDY, 0),
+        (PageFlags::empty(), 0),
+        (PageFlags::empty(), 0),
+        (PageFlags::empty(), 0),
+        (PageFlags::PAGETABLE, 1),
+        (PageFlags::NOPAGE, 0),
+    ];
+    let flags = frames.iter().map(|(flags, _)| flags.bits()).collect();
+    let counts = frames.iter().map(|(_, count)| *count).collect();
+    MeminfoCollector::with_source(MemorySource::new(counts, flags, 4096).unwrap())
+}
+
+#[test]
+fn encodes_all_counters() {
+    let (stats, _) = collector().refresh().unwrap();
+    let samples = parse(&exporter::encode(&stats, 4096));
+
+    assert_eq!(samples["meminfo_page_size_bytes"][&vec![]], 4096.0);
+    assert_eq!(frames(&samples, "total", "present"), 9.0);
+    assert_eq!(frames(&samples, "lru", "total"), 3.0);
+    assert_eq!(frames(&samples, "lru", "active"), 2.0);
+    assert_eq!(frames(&samples, "mmap", "anon"), 2.0);
+    assert_eq!(frames(&samples, "mmap", "file"), 1.0);
+    assert_eq!(frames(&samples, "buddy", "free"), 4.0);
+    let order = |order: &str| vec![("order".to_string(), order.to_string())];
+    assert_eq!(samples["meminfo_buddy_free_blocks"][&order("2")], 1.0);
+    assert_eq!(frames(&samples, "kernel", "slab"), 1.0);
+    assert_eq!(frames(&samples, "kernel", "pagetable"), 1.0);
+    assert_eq!(frames(&samples, "other", "ksm"), 0.0);
+    assert_eq!(frames(&samples, "other", "poisoned"), 0.0);
+    assert_eq!(frames(&samples, "huge", "total"), 0.0);
+    assert_eq!(samples["meminfo_page_frame_anomalies"][&vec![]], 0.0);
+}
+
+#[test]
+fn labels_every_frame_counter() {
+    let (stats, _) = collector().refresh().unwrap();
+    let samples = parse(&exporter::encode(&stats, 4096));
+
+    for labels in samples["meminfo_page_frames"].keys() {
+        let names: Vec<_> = labels.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["category", "subcategory"]);
+    }
+    for category in &["total", "lru", "mmap", "free", "buddy", "huge", "kernel"] {
+        assert!(
+            samples["meminfo_page_frames"]
+                .keys()
+                .any(|labels| labels[0].1 == *category),
+            "no counter for {}",
+            category
+        );
+    }
+}
+
+/// Sends `request` to a fresh exporter and returns the status line, headers and body
+/// of the response.
+fn exchange(request: &str) -> (String, HashMap<String, String>, String) {
+    let (mut client, server) = UnixStream::pair().unwrap();
+    client.write_all(request.as_bytes()).unwrap();
+    Exporter::new(collector()).handle(server).unwrap();
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .expect("response has a head");
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap().to_string();
+    let headers = lines
+        .map(|line| {
+            let (name, value) = line.split_once(": ").expect("header has a value");
+            (name.to_string(), value.to_string())
+        })
+        .collect();
+    (status, headers, body.to_string())
+}
+
+#[test]
+fn serves_metrics_over_http() {
+    let (status, headers, body) =
+        exchange("GET /metrics HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n");
+
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(headers["Content-Type"], CONTENT_TYPE);
+    assert_eq!(headers["Content-Length"], body.len().to_string());
+    assert_eq!(frames(&parse(&body), "total", "present"), 9.0);
+}
+
+#[test]
+fn rejects_other_requests() {
+    let (status, _, _) = exchange("GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+
+    let (status, headers, _) = exchange("POST /metrics HTTP/1.1\r\n\r\n");
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    assert_eq!(headers["Allow"], "GET");
+
+    let (status, _, _) = exchange("hello\r\n\r\n");
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+}
+
+#[test]
+fn replaces_stale_unix_socket() {
+    let path = std::env::temp_dir().join(format!("meminfo-exporter-{}.sock", std::process::id()));
+    let address = path.to_str().unwrap();
+    drop(Listener::bind(address).unwrap());
+    // The first listener left its socket behind.
+    assert!(path.exists());
+    let listener = Listener::bind(address).unwrap();
+
+    let mut client = UnixStream::connect(&path).unwrap();
+    client.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+    let server = match &listener {
+        Listener::Unix(listener) => listener.accept().unwrap().0,
+        Listener::Tcp(_) => panic!("bound a TCP listener to a path"),
+    };
+    Exporter::new(collector()).handle(server).unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,84 @@
+use meminfo_server::frame_table::{PageFrameTable, SATURATED_REFERENCE_COUNT};
+use meminfo_server::proc_page::{PageFlags, PageFrame};
+
+const SLAB: u64 = PageFlags::SLAB.bits();
+
+/// A table of the frames at `pfns`, each flagged as slab and referenced once per
+/// PFN.
+fn table(pfns: &[u64]) -> PageFrameTable {
+    let mut table = PageFrameTable::new();
+    for &pfn in pfns {
+        table.push(pfn, SLAB, pfn);
+    }
+    table
+}
+
+fn frame(reference_count: u64) -> PageFrame {
+    PageFrame {
+        reference_count,
+        flags: PageFlags::SLAB,
+    }
+}
+
+#[test]
+fn looks_up_frames_across_holes() {
+    let table = table(&[0, 1, 2, 10, 11]);
+
+    assert_eq!(table.len(), 5);
+    assert_eq!(table.end_pfn(), 12);
+    assert_eq!(table.get(1), Some(frame(1)));
+    assert_eq!(table.get(10), Some(frame(10)));
+    assert_eq!(table.get(3), None);
+    assert_eq!(table.get(9), None);
+    assert_eq!(table.get(12), None);
+    assert_eq!(table.present_ranges().collect::<Vec<_>>(), [0..3, 10..12]);
+    let pfns: Vec<_> = table.iter().map(|(pfn, _)| pfn).collect();
+    assert_eq!(pfns, [0, 1, 2, 10, 11]);
+}
+
+#[test]
+fn appends_tables_joining_adjacent_runs() {
+    let mut joined = table(&[0, 1, 2]);
+    joined.append(table(&[3, 4, 8]));
+
+    assert_eq!(joined, table(&[0, 1, 2, 3, 4, 8]));
+    assert_eq!(joined.present_ranges().collect::<Vec<_>>(), [0..5, 8..9]);
+    assert_eq!(joined.get(4), Some(frame(4)));
+    assert_eq!(joined.get(8), Some(frame(8)));
+}
+
+#[test]
+fn appends_tables_after_a_hole() {
+    let mut joined = table(&[0, 1]);
+    joined.append(table(&[5, 6]));
+
+    assert_eq!(joined.present_ranges().collect::<Vec<_>>(), [0..2, 5..7]);
+    assert_eq!(joined.get(6), Some(frame(6)));
+    assert_eq!(joined.get(2), None);
+
+    let mut empty = PageFrameTable::new();
+    empty.append(joined.clone());
+    assert_eq!(empty, joined);
+}
+
+#[test]
+fn saturates_reference_counts() {
+    let mut table = PageFrameTable::new();
+    table.push(0, SLAB, 1000);
+
+    assert_eq!(table.get(0), Some(frame(SATURATED_REFERENCE_COUNT)));
+}
+
+#[test]
+fn keeps_the_flags_it_is_documented_to() {
+    let kept = PageFlags::LOCKED
+        | PageFlags::PAGETABLE
+        | PageFlags::RESERVED
+        | PageFlags::MLOCKED
+        | PageFlags::PRIVATE_2;
+    let dropped = PageFlags::ARCH | PageFlags::UNCACHED | PageFlags::SOFTDIRTY;
+    let mut table = PageFrameTable::new();
+    table.push(0, (kept | dropped).bits() | 1 << 63, 1);
+
+    assert_eq!(table.get(0).unwrap().flags, kept);
+}
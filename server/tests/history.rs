@@ -0,0 +1,97 @@
+use std::io;
+use std::time::Duration;
+
+use meminfo_server::history::{History, HistorySample, Tier};
+
+/// Five samples at one per second and five at one per minute.
+fn history() -> History {
+    History::new(&[
+        Tier {
+            resolution: Duration::from_secs(1),
+            span: Duration::from_secs(5),
+        },
+        Tier {
+            resolution: Duration::from_secs(60),
+            span: Duration::from_secs(5 * 60),
+        },
+    ])
+}
+
+fn sample(timestamp_ms: u64) -> HistorySample {
+    HistorySample {
+        timestamp_ms,
+        ..Default::default()
+    }
+}
+
+/// A history of a sample every 500 ms for 500 seconds.
+fn recorded() -> History {
+    let mut history = history();
+    for i in 0..1000 {
+        history.record(sample(i * 500));
+    }
+    history
+}
+
+fn timestamps(samples: &[HistorySample]) -> Vec<u64> {
+    samples.iter().map(|sample| sample.timestamp_ms).collect()
+}
+
+#[test]
+fn keeps_the_latest_sample_per_interval() {
+    let mut history = history();
+    assert!(history.is_empty());
+    history.record(sample(1000));
+    history.record(sample(1500));
+
+    assert_eq!(timestamps(&history.query(0, u64::MAX)), [1500]);
+}
+
+#[test]
+fn drops_samples_from_the_future_after_the_clock_was_set_back() {
+    let mut history = history();
+    history.record(sample(10_000));
+    history.record(sample(5_000));
+
+    assert_eq!(timestamps(&history.query(0, u64::MAX)), [5_000]);
+}
+
+#[test]
+fn queries_the_finest_tier_reaching_back() {
+    let history = recorded();
+
+    assert_eq!(
+        timestamps(&history.query(0, u64::MAX)),
+        [299_500, 359_500, 419_500, 479_500, 495_500, 496_500, 497_500, 498_500, 499_500]
+    );
+    assert_eq!(
+        timestamps(&history.query(400_000, 497_000)),
+        [419_500, 479_500, 495_500, 496_500]
+    );
+    assert!(history.query(500_000, u64::MAX).is_empty());
+}
+
+#[test]
+fn reads_back_what_was_written() {
+    let history = recorded();
+    let mut bytes = Vec::new();
+    history.write(&mut bytes).unwrap();
+
+    let mut restored = History::default();
+    restored.read_from(&bytes[..]).unwrap();
+    assert_eq!(restored.query(0, u64::MAX), history.query(0, u64::MAX));
+}
+
+#[test]
+fn rejects_other_data() {
+    let mut bytes = Vec::new();
+    recorded().write(&mut bytes).unwrap();
+    bytes[8] = 2;
+
+    let mut restored = history();
+    let err = restored.read_from(&bytes[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = restored.read_from(&b"MEMSNAP\0\x01\0\0\0"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(restored.is_empty());
+}
@@ -0,0 +1,107 @@
+use std::io;
+
+use meminfo_server::proc_page::PageFlags;
+use meminfo_server::snapshot::{HostMetadata, NumaNode, Snapshot, Zone};
+use meminfo_server::source::{MemorySource, PageFrameSource, CHUNK_FRAMES};
+use meminfo_server::MeminfoCollector;
+
+/// Frames of a few kinds spanning more than one chunk, ending in a hole.
+fn source() -> MemorySource {
+    let anon = PageFlags::MMAP | PageFlags::ANON | PageFlags::LRU;
+    let kinds = [
+        (anon.bits(), 1),
+        (PageFlags::SLAB.bits(), 1),
+        (PageFlags::BUDDY.bits(), 0),
+        (0, 0),
+        // An anomaly, which the snapshot keeps as well.
+        (PageFlags::KSM.bits(), 1),
+    ];
+    let frames = CHUNK_FRAMES + 1000;
+    let (mut flags, mut counts): (Vec<_>, Vec<_>) =
+        (0..frames).map(|pfn| kinds[pfn % kinds.len()]).unzip();
+    flags[frames - 1] = PageFlags::NOPAGE.bits();
+    counts[frames - 1] = 0;
+    let cgroups = (0..frames as u64).map(|pfn| pfn % 3).collect();
+    MemorySource::new(counts, flags, 4096)
+        .unwrap()
+        .with_cgroups(cgroups)
+        .unwrap()
+}
+
+fn metadata() -> HostMetadata {
+    HostMetadata {
+        hostname: "test".to_string(),
+        kernel_release: "5.10.0-1-amd64".to_string(),
+        kernel_version: "#1 SMP Debian 5.10.4-1 (2020-12-31)".to_string(),
+        machine: "x86_64".to_string(),
+        page_size: 4096,
+        taken_at: 1_609_459_200,
+        nodes: vec![NumaNode {
+            id: 0,
+            cpus: "0-3".to_string(),
+            zones: vec![Zone {
+                name: "Normal".to_string(),
+                start_pfn: 0,
+                spanned: CHUNK_FRAMES as u64 + 1000,
+                present: CHUNK_FRAMES as u64 + 999,
+            }],
+        }],
+    }
+}
+
+#[test]
+fn reads_back_what_was_written() {
+    let source = source();
+    let mut collector = MeminfoCollector::with_source(source.clone());
+    let mut bytes = Vec::new();
+    collector.write_snapshot(&mut bytes, &metadata()).unwrap();
+    let (stats, anomalies) = collector.refresh().unwrap();
+    assert!(!anomalies.is_empty());
+
+    let snapshot = Snapshot::read(&bytes[..]).unwrap();
+    assert_eq!(snapshot.metadata, metadata());
+    assert_eq!(snapshot.stats, stats);
+    assert_eq!(snapshot.anomalies, anomalies);
+
+    let frames = CHUNK_FRAMES + 1000;
+    let read_back = |source: &MemorySource| {
+        let (mut flags, mut counts, mut cgroups) =
+            (vec![0; frames], vec![0; frames], vec![0; frames]);
+        source.read_flags(0, &mut flags).unwrap();
+        source.read_counts(0, &mut counts).unwrap();
+        source.read_cgroups(0, &mut cgroups).unwrap();
+        (flags, counts, cgroups)
+    };
+    assert_eq!(read_back(&snapshot.source), read_back(&source));
+
+    let mut reclassified = MeminfoCollector::with_source(snapshot.source);
+    assert_eq!(reclassified.refresh().unwrap(), (stats, anomalies));
+    assert_eq!(reclassified.page_frames(), collector.page_frames());
+}
+
+#[test]
+fn rejects_other_data() {
+    let err = Snapshot::read(&b"MEMHIST\0\x01\0\0\0"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let mut bytes = Vec::new();
+    MeminfoCollector::with_source(source())
+        .write_snapshot(&mut bytes, &metadata())
+        .unwrap();
+    bytes[8] = 2;
+    let err = Snapshot::read(&bytes[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_more_frames_than_allowed() {
+    let mut bytes = Vec::new();
+    MeminfoCollector::with_source(source())
+        .write_snapshot(&mut bytes, &metadata())
+        .unwrap();
+
+    let frames = CHUNK_FRAMES as u64 + 1000;
+    assert!(Snapshot::read_limited(&bytes[..], frames).is_ok());
+    let err = Snapshot::read_limited(&bytes[..], frames - 1).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}